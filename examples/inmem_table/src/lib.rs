@@ -1,5 +1,6 @@
 use lazy_static::lazy_static;
 use pgx::*;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::RwLock;
 
 pg_module_magic!();
@@ -8,6 +9,9 @@ lazy_static! {
     static ref TABLE: RwLock<Vec<User>> = RwLock::new(vec![]);
 }
 
+// Number of the next generated id
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug, Default, Clone)]
 struct User {
     id: String,
@@ -16,21 +20,19 @@ struct User {
 }
 
 impl User {
-    pub fn from_tuples(tuples: Vec<pgx_fdw::Tuple>) -> Self {
-        let row = tuples
+    pub fn from_tuples(tuples: Vec<pgx_fdw::Tuple>) -> pgx_fdw::FdwResult<Self> {
+        tuples
             .iter()
             .try_fold(User::default(), |mut t, (name, datum, typoid)| {
                 match name.to_string().as_str() {
-                    "id" => t.id = into_value::<String>(*datum, *typoid).unwrap(),
-                    "name" => t.name = into_value::<String>(*datum, *typoid).unwrap(),
-                    "email" => t.email = into_value::<String>(*datum, *typoid).unwrap(),
-                    _ => error!(""),
+                    "id" => t.id = into_value::<String>(*datum, *typoid).unwrap_or_default(),
+                    "name" => t.name = into_value::<String>(*datum, *typoid).unwrap_or_default(),
+                    "email" => t.email = into_value::<String>(*datum, *typoid).unwrap_or_default(),
+                    _ => return Err(unknown_column(name)),
                 }
 
-                Some(t)
-            });
-
-        row.unwrap()
+                Ok(t)
+            })
     }
     pub fn merge(&mut self, other: &Self) {
         if other.id != String::new() {
@@ -45,6 +47,55 @@ impl User {
             self.email = other.email.clone();
         }
     }
+
+    /// Generates an id for users inserted without one
+    pub fn with_generated_id(mut self) -> Self {
+        if self.id.is_empty() {
+            self.id = format!("user-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        }
+
+        self
+    }
+
+    pub fn into_tuples(self) -> Vec<pgx_fdw::Tuple> {
+        let text = PgOid::from(pg_sys::TEXTOID);
+
+        vec![
+            ("id".into(), self.id.into_datum(), text),
+            ("name".into(), self.name.into_datum(), text),
+            ("email".into(), self.email.into_datum(), text),
+        ]
+    }
+
+    /// Whether the user has every id of `ids`, which comes from `lookup_ids`
+    pub fn matches(&self, ids: &[Option<String>]) -> bool {
+        ids.iter().all(|id| id.as_ref() == Some(&self.id))
+    }
+
+    pub fn into_row(self) -> Vec<String> {
+        vec![self.id, self.name, self.email]
+    }
+}
+
+/// The ids `quals` look users up by, all of them `id = ...` quals accepted by `supports_qual`
+fn lookup_ids(quals: &[pgx_fdw::Qual]) -> pgx_fdw::FdwResult<Vec<Option<String>>> {
+    let ids: Vec<Option<String>> = quals
+        .iter()
+        .filter_map(|qual| match qual {
+            pgx_fdw::Qual::Op { value, .. } => Some(value.value::<String>()),
+            _ => None,
+        })
+        .collect();
+
+    if ids.iter().any(|id| id.as_deref() == Some("")) {
+        return Err(pgx_fdw::FdwError::new(
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            "user ids can't be empty",
+        )
+        .with_hint("Look users up by a non-empty id."));
+    }
+
+    Ok(ids)
 }
 
 fn into_value<T: FromDatum>(datum: Option<pg_sys::Datum>, typoid: pgx::PgOid) -> Option<T> {
@@ -53,58 +104,104 @@ fn into_value<T: FromDatum>(datum: Option<pg_sys::Datum>, typoid: pgx::PgOid) ->
         None => None,
     }
 }
+fn unknown_column(name: &str) -> pgx_fdw::FdwError {
+    pgx_fdw::FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_INVALID_COLUMN_NAME,
+        format!("unknown column \"{}\"", name),
+    )
+}
+
 struct InMemTable {}
 
 impl pgx_fdw::ForeignData for InMemTable {
     type Item = String;
     type RowIterator = std::vec::IntoIter<Vec<Self::Item>>;
 
-    fn begin(_opts: &pgx_fdw::FdwOptions) -> Self {
-        InMemTable {}
+    fn begin(_opts: &pgx_fdw::FdwOptions) -> pgx_fdw::FdwResult<Self> {
+        Ok(InMemTable {})
+    }
+
+    fn option_schema() -> Vec<pgx_fdw::OptionSpec> {
+        use pgx_fdw::{OptionCatalog, OptionSpec, OptionType};
+
+        vec![
+            OptionSpec::new("table_option", OptionType::Text, &[OptionCatalog::Table]).required(),
+            OptionSpec::new(
+                "table_option2",
+                OptionType::Integer,
+                &[OptionCatalog::Table],
+            ),
+        ]
     }
 
     fn indices(_opts: &pgx_fdw::FdwOptions) -> Option<Vec<String>> {
         Some(vec![String::from("id")])
     }
 
-    fn execute(&mut self, _desc: &PgTupleDesc) -> Self::RowIterator {
+    fn supports_qual(_opts: &pgx_fdw::FdwOptions, qual: &pgx_fdw::Qual) -> bool {
+        matches!(qual, pgx_fdw::Qual::Op { column, operator, .. } if column == "id" && operator == "=")
+    }
+
+    fn execute(&mut self, scan: &pgx_fdw::Scan) -> pgx_fdw::FdwResult<Self::RowIterator> {
+        let ids = lookup_ids(&scan.quals)?;
         let rows: Vec<Vec<String>> = TABLE
             .read()
             .unwrap()
             .iter()
-            .map(|r| vec![r.id.clone(), r.name.clone(), r.email.clone()])
+            .filter(|r| r.matches(&ids))
+            .map(|r| r.clone().into_row())
             .collect();
 
-        rows.into_iter()
+        Ok(rows.into_iter())
     }
 
     fn insert(
         &self,
         _desc: &PgTupleDesc,
+        _column_opts: &pgx_fdw::ColumnOptions,
         tuple: Vec<pgx_fdw::Tuple>,
-    ) -> Option<Vec<pgx_fdw::Tuple>> {
-        let row = User::from_tuples(tuple);
+    ) -> pgx_fdw::FdwResult<Option<Vec<pgx_fdw::Tuple>>> {
+        let row = User::from_tuples(tuple)?.with_generated_id();
         let mut rows = TABLE.write().unwrap();
 
         rows.push(row.clone());
 
-        None
+        // The generated id is reported by `RETURNING`
+        Ok(Some(row.into_tuples()))
+    }
+
+    fn insert_batch(
+        &self,
+        _desc: &PgTupleDesc,
+        _column_opts: &pgx_fdw::ColumnOptions,
+        tuples: Vec<Vec<pgx_fdw::Tuple>>,
+    ) -> pgx_fdw::FdwResult<()> {
+        let batch = tuples
+            .into_iter()
+            .map(|tuple| User::from_tuples(tuple).map(User::with_generated_id))
+            .collect::<pgx_fdw::FdwResult<Vec<User>>>()?;
+        let mut rows = TABLE.write().unwrap();
+
+        rows.extend(batch);
+
+        Ok(())
     }
 
     fn update(
         &self,
         _desc: &PgTupleDesc,
+        _column_opts: &pgx_fdw::ColumnOptions,
         tuples: Vec<pgx_fdw::Tuple>,
         indices: Vec<pgx_fdw::Tuple>,
-    ) -> Option<Vec<pgx_fdw::Tuple>> {
+    ) -> pgx_fdw::FdwResult<Option<Vec<pgx_fdw::Tuple>>> {
         if let Some((name, datum, oid)) = indices.first() {
             let fun = match name.to_string().as_str() {
                 "id" => |u: &User| u.id == into_value::<String>(*datum, *oid).unwrap(),
-                _ => error!(""),
+                _ => return Err(unknown_column(name)),
             };
 
             let mut rows = TABLE.write().unwrap();
-            let new_row = User::from_tuples(tuples);
+            let new_row = User::from_tuples(tuples)?;
             let positions: Vec<usize> = rows
                 .iter()
                 .enumerate()
@@ -119,28 +216,89 @@ impl pgx_fdw::ForeignData for InMemTable {
             }
         }
 
-        None
+        Ok(None)
     }
 
     fn delete(
         &self,
         _desc: &PgTupleDesc,
+        _column_opts: &pgx_fdw::ColumnOptions,
         tuples: Vec<pgx_fdw::Tuple>,
-    ) -> Option<Vec<pgx_fdw::Tuple>> {
+    ) -> pgx_fdw::FdwResult<Option<Vec<pgx_fdw::Tuple>>> {
         if let Some((name, datum, oid)) = tuples.first() {
             match name.to_string().as_str() {
                 "id" => {
                     let predicate = |u: &User| u.id == into_value::<String>(*datum, *oid).unwrap();
                     let mut rows = TABLE.write().unwrap();
                     let vec = std::mem::replace(&mut *rows, vec![]);
+                    let (deleted, kept): (Vec<User>, Vec<User>) =
+                        vec.into_iter().partition(|r| predicate(r));
+
+                    *rows = kept;
+
+                    // The deleted row is reported by `RETURNING`
+                    return Ok(deleted.into_iter().next().map(User::into_tuples));
+                }
+                _ => return Err(unknown_column(name)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn supports_direct_modify(_opts: &pgx_fdw::FdwOptions, modify: &pgx_fdw::DirectModify) -> bool {
+        // Every column is text
+        modify
+            .assignments
+            .iter()
+            .all(|assignment| assignment.value.type_oid() == PgOid::from(pg_sys::TEXTOID))
+    }
+
+    fn modify(
+        &mut self,
+        _desc: &PgTupleDesc,
+        _column_opts: &pgx_fdw::ColumnOptions,
+        modify: &pgx_fdw::DirectModify,
+    ) -> pgx_fdw::FdwResult<(u64, Option<Self::RowIterator>)> {
+        let ids = lookup_ids(&modify.quals)?;
+        let mut rows = TABLE.write().unwrap();
+        let mut modified = vec![];
 
-                    *rows = vec.into_iter().filter(|r| !predicate(r)).collect();
+        match modify.kind {
+            pgx_fdw::ModifyKind::Delete => {
+                let (deleted, kept) = std::mem::take(&mut *rows)
+                    .into_iter()
+                    .partition(|r| r.matches(&ids));
+
+                *rows = kept;
+                modified = deleted;
+            }
+            pgx_fdw::ModifyKind::Update => {
+                for row in rows.iter_mut().filter(|r| r.matches(&ids)) {
+                    for assignment in &modify.assignments {
+                        let value = assignment.value.value::<String>().unwrap_or_default();
+
+                        match assignment.column.as_str() {
+                            "id" => row.id = value,
+                            "name" => row.name = value,
+                            "email" => row.email = value,
+                            column => return Err(unknown_column(column)),
+                        }
+                    }
+
+                    modified.push(row.clone());
                 }
-                _ => error!(""),
             }
         }
 
-        None
+        let count = modified.len() as u64;
+        let returned: Vec<Vec<String>> = modified.into_iter().map(User::into_row).collect();
+
+        if modify.returning {
+            Ok((count, Some(returned.into_iter())))
+        } else {
+            Ok((count, None))
+        }
     }
 }
 
@@ -152,9 +310,17 @@ fn in_mem_table_handler() -> pg_sys::Datum {
     pgx_fdw::FdwState::<InMemTable>::into_datum()
 }
 
+/// ```sql
+/// CREATE FUNCTION in_mem_table_validator(text[], oid) RETURNS void LANGUAGE c AS 'MODULE_PATHNAME', 'in_mem_table_validator_wrapper';
+/// ```
+#[pg_extern]
+fn in_mem_table_validator(options: Vec<Option<String>>, catalog: pg_sys::Oid) {
+    pgx_fdw::FdwState::<InMemTable>::validate(options, catalog)
+}
+
 extension_sql!(
     r#"
-    CREATE FOREIGN DATA WRAPPER in_mem_table_handler handler in_mem_table_handler NO VALIDATOR;
+    CREATE FOREIGN DATA WRAPPER in_mem_table_handler handler in_mem_table_handler VALIDATOR in_mem_table_validator;
     CREATE SERVER in_mem_table_srv foreign data wrapper in_mem_table_handler;
     create foreign table users (
         id text,
@@ -166,3 +332,329 @@ extension_sql!(
     );
 "#
 );
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_rescan_nested_loop() {
+        Spi::run(
+            "INSERT INTO users VALUES ('rescan-1', 'a', 'a@example.com'), ('rescan-2', 'b', 'b@example.com')",
+        );
+        Spi::run("CREATE TEMP TABLE lookup (id text)");
+        Spi::run("INSERT INTO lookup VALUES ('rescan-1'), ('rescan-2'), ('rescan-3')");
+        Spi::run("SET enable_hashjoin = off");
+        Spi::run("SET enable_mergejoin = off");
+
+        // The foreign scan is restarted for every row of `lookup`
+        let joined =
+            Spi::get_one::<i64>("SELECT count(*) FROM lookup l JOIN users u ON u.id = l.id");
+        let correlated = Spi::get_one::<i64>(
+            "SELECT count((SELECT u.name FROM users u WHERE u.id = l.id)) FROM lookup l",
+        );
+
+        assert_eq!(joined, Some(2));
+        assert_eq!(correlated, Some(2));
+    }
+
+    /// The SQLSTATE, message and hint of the error raised by `sql`, all `None` when it succeeds
+    fn error(sql: &str) -> (Option<String>, Option<String>, Option<String>) {
+        Spi::run(
+            r#"
+            CREATE OR REPLACE FUNCTION pg_temp.error(sql text, OUT sqlstate text, OUT message text, OUT hint text)
+            LANGUAGE plpgsql AS $$
+            BEGIN
+                EXECUTE sql;
+            EXCEPTION WHEN others THEN
+                GET STACKED DIAGNOSTICS
+                    sqlstate = RETURNED_SQLSTATE,
+                    message = MESSAGE_TEXT,
+                    hint = PG_EXCEPTION_HINT;
+            END
+            $$
+            "#,
+        );
+
+        Spi::get_three_with_args(
+            "SELECT * FROM pg_temp.error($1)",
+            vec![(PgBuiltInOids::TEXTOID.oid(), sql.into_datum())],
+        )
+    }
+
+    #[pg_test]
+    fn test_execute_error() {
+        assert_eq!(
+            error("SELECT * FROM users WHERE id = ''"),
+            (
+                Some("22023".into()),
+                Some("user ids can't be empty".into()),
+                Some("Look users up by a non-empty id.".into())
+            )
+        );
+    }
+
+    #[pg_test]
+    fn test_validator_suggests_option() {
+        assert_eq!(
+            error("ALTER FOREIGN TABLE users OPTIONS (ADD table_optoin '3')").2,
+            Some("Perhaps you meant the option \"table_option\".".into())
+        );
+        assert_eq!(
+            error("ALTER FOREIGN TABLE users OPTIONS (ADD batch_size '10')").2,
+            None
+        );
+    }
+
+    #[pg_test(error = "option \"table_option\" is required")]
+    fn test_validator_required_option() {
+        Spi::run(
+            "CREATE FOREIGN TABLE missing_option (id text) SERVER in_mem_table_srv OPTIONS (table_option2 '2')",
+        );
+    }
+
+    #[pg_test(error = "option \"table_option2\" requires an integer value")]
+    fn test_validator_option_type() {
+        Spi::run("ALTER FOREIGN TABLE users OPTIONS (SET table_option2 'two')");
+    }
+
+    #[pg_test]
+    fn test_insert_batch() {
+        Spi::run("ALTER FOREIGN TABLE users OPTIONS (ADD batch_size '2')");
+        Spi::run("CREATE TEMP TABLE seen (n bigint)");
+        Spi::run(
+            r#"
+            CREATE FUNCTION count_batched() RETURNS trigger LANGUAGE plpgsql AS $$
+            BEGIN
+                INSERT INTO seen SELECT count(*) FROM users WHERE id LIKE 'batch-%';
+                RETURN NULL;
+            END
+            $$
+            "#,
+        );
+
+        // Inserted two at a time, the last row when the statement ends
+        Spi::run(
+            "INSERT INTO users SELECT 'batch-' || i, 'b', 'b@example.com' FROM generate_series(1, 5) i",
+        );
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM users WHERE id LIKE 'batch-%'"),
+            Some(5)
+        );
+
+        // The trigger has to see every row of the statement
+        Spi::run(
+            "CREATE TRIGGER count_batched AFTER INSERT ON users FOR EACH STATEMENT EXECUTE FUNCTION count_batched()",
+        );
+        Spi::run(
+            "INSERT INTO users SELECT 'batch-' || i, 'b', 'b@example.com' FROM generate_series(6, 10) i",
+        );
+        assert_eq!(Spi::get_one::<i64>("SELECT n FROM seen"), Some(10));
+    }
+
+    /// The text `EXPLAIN` of `sql`
+    fn explain(sql: &str) -> String {
+        Spi::connect(|client| {
+            let plan: Vec<String> = client
+                .select(&format!("EXPLAIN {}", sql), None, None)
+                .filter_map(|row| row.by_ordinal(1).ok()?.value::<String>())
+                .collect();
+
+            Ok(Some(plan.join("\n")))
+        })
+        .unwrap()
+    }
+
+    /// Number of rows processed by `sql`
+    fn processed(sql: &str) -> i64 {
+        Spi::connect(|mut client| Ok(Some(client.update(sql, None, None).len() as i64))).unwrap()
+    }
+
+    #[pg_test]
+    fn test_direct_delete() {
+        Spi::run(
+            "INSERT INTO users VALUES ('delete-1', 'a', 'a@example.com'), ('delete-2', 'b', 'b@example.com')",
+        );
+
+        assert!(explain("DELETE FROM users WHERE id = 'delete-1'").contains("Foreign Delete"));
+        assert_eq!(processed("DELETE FROM users WHERE id = 'delete-1'"), 1);
+        assert_eq!(processed("DELETE FROM users WHERE id = 'delete-1'"), 0);
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM users WHERE id LIKE 'delete-%'"),
+            Some(1)
+        );
+    }
+
+    #[pg_test]
+    fn test_direct_update_returning() {
+        Spi::run("INSERT INTO users VALUES ('update-1', 'a', 'a@example.com')");
+        // A generic plan leaves `$1` a `QualValue::Param` until the statement runs
+        Spi::run("SET plan_cache_mode = force_generic_plan");
+        Spi::run(
+            "PREPARE rename(text) AS UPDATE users SET name = $1 WHERE id = 'update-1' RETURNING *",
+        );
+
+        assert!(explain("EXECUTE rename('renamed')").contains("Foreign Update"));
+        assert_eq!(
+            Spi::get_three::<String, String, String>("EXECUTE rename('renamed')"),
+            (
+                Some("update-1".into()),
+                Some("renamed".into()),
+                Some("a@example.com".into())
+            )
+        );
+        assert_eq!(
+            Spi::get_one::<String>("SELECT name FROM users WHERE id = 'update-1'"),
+            Some("renamed".into())
+        );
+    }
+
+    #[pg_test]
+    fn test_update_from_row_not_pushed_down() {
+        Spi::run("INSERT INTO users VALUES ('fallback-1', 'a', 'a@example.com')");
+
+        // The new name depends on the current one, so rows are fetched and updated one by one
+        let sql = "UPDATE users SET name = name || '!' WHERE id = 'fallback-1'";
+        let plan = explain(sql);
+
+        assert!(plan.contains("Foreign Scan"));
+        assert!(!plan.contains("Foreign Update"));
+        assert_eq!(processed(sql), 1);
+        assert_eq!(
+            Spi::get_one::<String>("SELECT name FROM users WHERE id = 'fallback-1'"),
+            Some("a!".into())
+        );
+    }
+
+    #[pg_test]
+    fn test_returning() {
+        let id = Spi::get_one::<String>(
+            "INSERT INTO users (name, email) VALUES ('generated', 'g@example.com') RETURNING id",
+        );
+
+        assert!(matches!(&id, Some(id) if id.starts_with("user-")));
+        assert_eq!(
+            Spi::get_one_with_args::<String>(
+                "SELECT name FROM users WHERE id = $1",
+                vec![(PgBuiltInOids::TEXTOID.oid(), id.into_datum())]
+            ),
+            Some("generated".into())
+        );
+
+        // Not pushed down, so the row is deleted with `delete`
+        Spi::run("INSERT INTO users VALUES ('returning-1', 'gone', 'gone@example.com')");
+        assert_eq!(
+            Spi::get_three::<String, String, String>(
+                "DELETE FROM users WHERE name = 'gone' RETURNING *"
+            ),
+            (
+                Some("returning-1".into()),
+                Some("gone".into()),
+                Some("gone@example.com".into())
+            )
+        );
+    }
+
+    #[pg_test]
+    fn test_copy() {
+        Spi::run(
+            "COPY users FROM PROGRAM 'echo copy-1,copied,copied@example.com' WITH (FORMAT csv)",
+        );
+
+        assert_eq!(
+            Spi::get_one::<String>("SELECT name FROM users WHERE id = 'copy-1'"),
+            Some("copied".into())
+        );
+    }
+
+    #[pg_test]
+    fn test_route_into_foreign_partition() {
+        Spi::run("CREATE TABLE routed (id text, name text, email text) PARTITION BY LIST (name)");
+        Spi::run(
+            "CREATE FOREIGN TABLE routed_foreign PARTITION OF routed FOR VALUES IN ('foreign') SERVER in_mem_table_srv OPTIONS (table_option '1')",
+        );
+        Spi::run("CREATE TABLE routed_local PARTITION OF routed FOR VALUES IN ('local')");
+
+        Spi::run(
+            "INSERT INTO routed VALUES ('routed-1', 'foreign', 'a@example.com'), ('routed-2', 'local', 'b@example.com')",
+        );
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM routed_foreign WHERE id = 'routed-1'"),
+            Some(1)
+        );
+
+        // Partitions are updated in order, `routed_foreign` is done when the row moves into it
+        Spi::run("UPDATE routed SET name = 'foreign' WHERE id = 'routed-2'");
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM routed_foreign WHERE id = 'routed-2'"),
+            Some(1)
+        );
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM routed_local"),
+            Some(0)
+        );
+    }
+
+    #[pg_test(error = "cannot route tuples into foreign table to be updated \"routed_remote\"")]
+    fn test_route_into_partition_updated_later() {
+        Spi::run("CREATE TABLE routed (id text, name text, email text) PARTITION BY LIST (name)");
+        Spi::run("CREATE TABLE routed_local PARTITION OF routed FOR VALUES IN ('local')");
+        Spi::run(
+            "CREATE FOREIGN TABLE routed_remote PARTITION OF routed FOR VALUES IN ('remote') SERVER in_mem_table_srv OPTIONS (table_option '1')",
+        );
+
+        // `routed_remote` is updated after `routed_local`, it would update the moved row again
+        Spi::run("INSERT INTO routed VALUES ('routed-3', 'local', 'c@example.com')");
+        Spi::run("UPDATE routed SET name = 'remote' WHERE id = 'routed-3'");
+    }
+
+    #[derive(pgx_fdw::FromOptions)]
+    struct TableOptions {
+        table_option: String,
+        #[option(rename = "table_option2")]
+        second: i64,
+        #[option(default = "100")]
+        batch_size: usize,
+        #[option(default)]
+        verbose: bool,
+        timeout: Option<std::time::Duration>,
+    }
+
+    #[derive(pgx_fdw::FromOptions)]
+    struct MissingOptions {
+        #[allow(dead_code)]
+        missing: String,
+    }
+
+    #[pg_test]
+    fn test_from_options() {
+        use pgx_fdw::FromOptions;
+
+        let rel = PgRelation::open_with_name_and_share_lock("users").unwrap();
+        let opts = pgx_fdw::FdwOptions::from_relation(&rel);
+        let options = TableOptions::from_options(&opts).unwrap();
+
+        assert_eq!(options.table_option, "1");
+        assert_eq!(options.second, 2);
+        assert_eq!(options.batch_size, 100);
+        assert!(!options.verbose);
+        assert_eq!(options.timeout, None);
+
+        assert_eq!(
+            MissingOptions::from_options(&opts)
+                .map(|_| ())
+                .unwrap_err()
+                .message,
+            "option \"missing\" is required"
+        );
+    }
+}
+
+#[cfg(test)]
+pub mod pg_test {
+    pub fn setup(_options: Vec<&str>) {}
+
+    pub fn postgresql_conf_options() -> Vec<&'static str> {
+        vec![]
+    }
+}
@@ -0,0 +1,154 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, GenericArgument, Lit, Meta, NestedMeta,
+    PathArguments, Type,
+};
+
+/// Implements `pgx_fdw::FromOptions` for a struct with named fields, reading every field from
+/// the option of the same name with `FdwOptions::get_value`. Field types have to implement
+/// `pgx_fdw::OptionValue`.
+///
+/// `Option<T>` fields are optional, other fields are required unless they have a default:
+///
+/// * `#[option(default)]` - `Default::default()` when the option isn't given
+/// * `#[option(default = "10")]` - parse `"10"` when the option isn't given
+/// * `#[option(rename = "name")]` - read the option `name` instead of the field's name
+#[proc_macro_derive(FromOptions, attributes(option))]
+pub fn derive_from_options(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match from_options(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+enum FieldDefault {
+    Required,
+    Trait,
+    Value(String),
+}
+
+fn from_options(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "FromOptions requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "FromOptions requires a struct with named fields",
+            ))
+        }
+    };
+
+    let mut initializers = vec![];
+
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named fields have an ident");
+        let mut name = ident.to_string();
+        let mut default = FieldDefault::Required;
+
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path.is_ident("option"))
+        {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => return Err(syn::Error::new_spanned(meta, "expected #[option(...)]")),
+            };
+
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default") => {
+                        default = FieldDefault::Trait;
+                    }
+                    NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("default") => {
+                        default = FieldDefault::Value(string_literal(&pair.lit)?);
+                    }
+                    NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("rename") => {
+                        name = string_literal(&pair.lit)?;
+                    }
+                    nested => {
+                        return Err(syn::Error::new_spanned(
+                            nested,
+                            "expected `default`, `default = \"...\"` or `rename = \"...\"`",
+                        ))
+                    }
+                }
+            }
+        }
+
+        let initializer = match (option_type(&field.ty), default) {
+            (Some(ty), _) => quote! { options.get_value::<#ty>(#name)? },
+            (None, FieldDefault::Required) => {
+                let ty = &field.ty;
+                quote! { options.require_value::<#ty>(#name)? }
+            }
+            (None, FieldDefault::Trait) => {
+                let ty = &field.ty;
+                quote! { options.get_value::<#ty>(#name)?.unwrap_or_default() }
+            }
+            (None, FieldDefault::Value(value)) => {
+                let ty = &field.ty;
+                quote! {
+                    match options.get_value::<#ty>(#name)? {
+                        Some(value) => value,
+                        None => ::pgx_fdw::FdwOptions::parse_value::<#ty>(#name, #value)?,
+                    }
+                }
+            }
+        };
+
+        initializers.push(quote! { #ident: #initializer });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::pgx_fdw::FromOptions for #ident #ty_generics #where_clause {
+            fn from_options(options: &::pgx_fdw::FdwOptions) -> ::pgx_fdw::FdwResult<Self> {
+                ::std::result::Result::Ok(#ident {
+                    #(#initializers,)*
+                })
+            }
+        }
+    })
+}
+
+fn string_literal(lit: &Lit) -> syn::Result<String> {
+    match lit {
+        Lit::Str(lit) => Ok(lit.value()),
+        lit => Err(syn::Error::new_spanned(lit, "expected a string literal")),
+    }
+}
+
+/// `T` of a field of type `Option<T>`
+fn option_type(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != "Option" {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,109 @@
+use pg_sys::*;
+use pgx::*;
+use std::ffi::CStr;
+
+// Not exported by `pg_sys`, from `pg_namespace_d.h` and `pg_aggregate.h`
+const PG_CATALOG_NAMESPACE: Oid = 11;
+const AGGKIND_NORMAL: std::os::raw::c_char = b'n' as std::os::raw::c_char;
+
+/// Grouping and aggregates of a query computed by `ForeignData::aggregate`, e.g.
+/// `SELECT region, count(*), max(price) FROM t GROUP BY region`
+#[derive(Debug, Clone)]
+pub struct Aggregation {
+    /// Columns of the `GROUP BY`, empty when the whole table is aggregated
+    pub group_by: Vec<String>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+/// A call to one of the built-in aggregate functions
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    /// Name of the aggregate function, e.g. `count` or `sum`
+    pub function: String,
+    /// The aggregated column, `None` for `count(*)`
+    pub column: Option<String>,
+    /// `function(DISTINCT column)`
+    pub distinct: bool,
+    /// Type of the aggregate's result, which the returned value has to be of
+    pub result_type: PgOid,
+}
+
+impl Aggregation {
+    /// The aggregation computing the columns of `scan_tlist`, which has the grouped columns
+    /// first followed by the `Aggref`s.
+    ///
+    /// Only single column arguments of plain aggregate calls are supported, without
+    /// `FILTER` or `ORDER BY`.
+    pub(crate) unsafe fn from_scan_tlist(
+        scan_tlist: *mut List,
+        desc: &PgTupleDesc,
+    ) -> Option<Self> {
+        let mut group_by = vec![];
+        let mut aggregates = vec![];
+
+        for tle in PgList::<TargetEntry>::from_pg(scan_tlist).iter_ptr() {
+            let expr = (*tle).expr as *mut Node;
+
+            if is_a(expr, NodeTag_T_Var) && aggregates.is_empty() {
+                group_by.push(Self::column(expr, desc)?);
+            } else if is_a(expr, NodeTag_T_Aggref) {
+                aggregates.push(Self::aggregate(expr as *mut Aggref, desc)?);
+            } else {
+                return None;
+            }
+        }
+
+        Some(Aggregation {
+            group_by,
+            aggregates,
+        })
+    }
+
+    unsafe fn aggregate(aggref: *mut Aggref, desc: &PgTupleDesc) -> Option<Aggregate> {
+        if (*aggref).aggkind != AGGKIND_NORMAL
+            || (*aggref).aggsplit != AggSplit_AGGSPLIT_SIMPLE
+            || (*aggref).aggvariadic
+            || !(*aggref).aggfilter.is_null()
+            || !(*aggref).aggorder.is_null()
+            || !(*aggref).aggdirectargs.is_null()
+            || get_func_namespace((*aggref).aggfnoid) != PG_CATALOG_NAMESPACE
+        {
+            return None;
+        }
+
+        let args = PgList::<TargetEntry>::from_pg((*aggref).args);
+        let column = match args.len() {
+            0 if (*aggref).aggstar => None,
+            1 => Some(Self::column((*args.get_ptr(0)?).expr as *mut Node, desc)?),
+            _ => return None,
+        };
+
+        let name = get_func_name((*aggref).aggfnoid);
+
+        if name.is_null() {
+            return None;
+        }
+
+        Some(Aggregate {
+            function: CStr::from_ptr(name).to_str().ok()?.into(),
+            column,
+            distinct: !(*aggref).aggdistinct.is_null(),
+            result_type: PgOid::from((*aggref).aggtype),
+        })
+    }
+
+    unsafe fn column(expr: *mut Node, desc: &PgTupleDesc) -> Option<String> {
+        if !is_a(expr, NodeTag_T_Var) {
+            return None;
+        }
+
+        let var = expr as *mut Var;
+
+        if (*var).varlevelsup != 0 || (*var).varattno <= 0 {
+            return None;
+        }
+
+        desc.get((*var).varattno as usize - 1)
+            .map(|attr| attr.name().into())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::cstring;
+use pgx::*;
+use std::fmt;
+
+pub type FdwResult<T> = Result<T, FdwError>;
+
+/// An error returned by a `ForeignData` implementation, raised as a Postgres `ERROR` with
+/// the given SQLSTATE, message, detail, hint and context.
+#[derive(Clone)]
+pub struct FdwError {
+    pub code: PgSqlErrorCode,
+    pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+    pub context: Option<String>,
+}
+
+impl FdwError {
+    pub fn new(code: PgSqlErrorCode, message: impl Into<String>) -> Self {
+        FdwError {
+            code,
+            message: message.into(),
+            detail: None,
+            hint: None,
+            context: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn with_context(mut self, context: impl Into<String>) -> Self {
+        self.context = Some(context.into());
+        self
+    }
+
+    /// The five character SQLSTATE of `code`, e.g. `HV000`
+    pub fn sqlstate(&self) -> String {
+        let code = self.code as i32;
+
+        (0..5)
+            .map(|i| (((code >> (6 * i)) & 0x3F) as u8 + b'0') as char)
+            .collect()
+    }
+
+    /// Raises the error with `ereport(ERROR, ...)`. The jump out of `errfinish` unwinds as a
+    /// panic, so callers have to run under `pg_sys::guard::guard`, as the callbacks do.
+    pub(crate) fn report(self) -> ! {
+        let format = cstring("%s");
+        let file = cstring(file!());
+        let function = cstring("FdwError::report");
+        let message = cstring(&self.message);
+        let detail = self.detail.as_deref().map(cstring);
+        let hint = self.hint.as_deref().map(cstring);
+        let context = self.context.as_deref().map(cstring);
+
+        unsafe {
+            #[cfg(any(feature = "pg10", feature = "pg11", feature = "pg12"))]
+            let reporting = pg_sys::errstart(
+                pg_sys::ERROR as i32,
+                file.as_ptr(),
+                line!() as i32,
+                function.as_ptr(),
+                std::ptr::null(),
+            );
+            #[cfg(feature = "pg13")]
+            let reporting = pg_sys::errstart(pg_sys::ERROR as i32, std::ptr::null());
+
+            if reporting {
+                pg_sys::errcode(self.code as i32);
+                pg_sys::errmsg(format.as_ptr(), message.as_ptr());
+
+                if let Some(detail) = &detail {
+                    pg_sys::errdetail(format.as_ptr(), detail.as_ptr());
+                }
+
+                if let Some(hint) = &hint {
+                    pg_sys::errhint(format.as_ptr(), hint.as_ptr());
+                }
+
+                if let Some(context) = &context {
+                    pg_sys::set_errcontext_domain(std::ptr::null());
+                    pg_sys::errcontext_msg(format.as_ptr(), context.as_ptr());
+                }
+
+                #[cfg(any(feature = "pg10", feature = "pg11", feature = "pg12"))]
+                pg_sys::errfinish(0);
+                #[cfg(feature = "pg13")]
+                pg_sys::errfinish(file.as_ptr(), line!() as i32, function.as_ptr());
+            }
+        }
+
+        // `errfinish` doesn't return for errors
+        unreachable!("ereport(ERROR) returned")
+    }
+}
+
+impl fmt::Debug for FdwError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FdwError")
+            .field("code", &self.sqlstate())
+            .field("message", &self.message)
+            .field("detail", &self.detail)
+            .field("hint", &self.hint)
+            .field("context", &self.context)
+            .finish()
+    }
+}
+
+impl fmt::Display for FdwError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FdwError {}
+
+impl From<String> for FdwError {
+    fn from(message: String) -> Self {
+        FdwError::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, message)
+    }
+}
+
+impl From<&str> for FdwError {
+    fn from(message: &str) -> Self {
+        FdwError::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, message)
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{cstring, Aggregation, DirectModify, Join, Scan};
+use pg_sys::*;
+use pgx::*;
+use std::ffi::CString;
+
+/// Output of `EXPLAIN` for a scan or modification, written by `ForeignData::explain`
+pub struct Explain {
+    es: *mut ExplainState,
+}
+
+impl Explain {
+    pub(crate) fn new(es: *mut ExplainState) -> Self {
+        Explain { es }
+    }
+
+    /// `EXPLAIN (VERBOSE)`, details like the query sent to the source belong here
+    pub fn verbose(&self) -> bool {
+        unsafe { (*self.es).verbose }
+    }
+
+    /// `EXPLAIN ANALYZE`, the statement was executed and runtime counters like the number of
+    /// requests made can be reported
+    pub fn analyze(&self) -> bool {
+        unsafe { (*self.es).analyze }
+    }
+
+    /// e.g. `Remote Query: SELECT ...`
+    pub fn property(&mut self, label: &str, value: &str) {
+        let (label, value) = (cstring(label), cstring(value));
+
+        unsafe { ExplainPropertyText(label.as_ptr(), value.as_ptr(), self.es) }
+    }
+
+    /// e.g. `Requests: 3`, or `Batch Size: 100 rows` with a `unit`
+    pub fn property_integer(&mut self, label: &str, unit: Option<&str>, value: i64) {
+        let (label, unit) = (cstring(label), unit.map(cstring));
+
+        unsafe { ExplainPropertyInteger(label.as_ptr(), unit_ptr(&unit), value, self.es) }
+    }
+
+    /// e.g. `Fetch Time: 1.234 ms` with `unit` `ms` and 3 `digits`
+    pub fn property_float(&mut self, label: &str, unit: Option<&str>, value: f64, digits: i32) {
+        let (label, unit) = (cstring(label), unit.map(cstring));
+
+        unsafe { ExplainPropertyFloat(label.as_ptr(), unit_ptr(&unit), value, digits, self.es) }
+    }
+
+    pub fn property_bool(&mut self, label: &str, value: bool) {
+        let label = cstring(label);
+
+        unsafe { ExplainPropertyBool(label.as_ptr(), value, self.es) }
+    }
+}
+
+/// The scan or modification being explained, as planned. `QualValue::Param`s are `None`.
+pub enum Planned<'a> {
+    /// A scan executed with `ForeignData::execute`
+    Scan(&'a Scan<'a>),
+    /// An aggregation of the rows of `scan` computed with `ForeignData::aggregate`
+    Aggregate {
+        scan: &'a Scan<'a>,
+        aggregation: &'a Aggregation,
+    },
+    /// A join computed with `ForeignData::join`
+    Join(&'a Join),
+    /// An `UPDATE` or `DELETE` executed with `ForeignData::modify`
+    DirectModify(&'a DirectModify),
+    /// Rows inserted with `ForeignData::insert` or `insert_batch`
+    Insert,
+    /// Rows fetched with `execute` and updated one at a time with `ForeignData::update`
+    Update,
+    /// Rows fetched with `execute` and deleted one at a time with `ForeignData::delete`
+    Delete,
+}
+
+fn unit_ptr(unit: &Option<CString>) -> *const std::os::raw::c_char {
+    unit.as_ref().map_or(std::ptr::null(), |unit| unit.as_ptr())
+}
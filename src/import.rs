@@ -0,0 +1,142 @@
+use crate::{cstring, FdwOption, FdwOptions};
+use pg_sys::*;
+use pgx::*;
+use std::ffi::CStr;
+
+/// An `IMPORT FOREIGN SCHEMA` statement handled by `ForeignData::import_schema`
+#[derive(Debug)]
+pub struct ImportSchema {
+    pub server_name: String,
+    pub server_opts: FdwOption,
+    /// Options of the user mapping of the current user, or else of the `PUBLIC` one
+    pub user_opts: FdwOption,
+    /// Schema of the source to import tables from
+    pub remote_schema: String,
+    pub filter: ImportFilter,
+    /// Options given with `IMPORT FOREIGN SCHEMA ... OPTIONS (...)`
+    pub options: FdwOption,
+}
+
+/// Tables selected by `LIMIT TO (...)` or `EXCEPT (...)`. Postgres skips tables that aren't
+/// selected, returning them anyway is only wasted work.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportFilter {
+    All,
+    LimitTo(Vec<String>),
+    Except(Vec<String>),
+}
+
+/// A foreign table to create, rendered as `CREATE FOREIGN TABLE`
+#[derive(Debug, Clone)]
+pub struct TableDefinition {
+    pub name: String,
+    pub columns: Vec<ColumnDefinition>,
+    pub options: FdwOption,
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnDefinition {
+    pub name: String,
+    /// A Postgres type as written in SQL, e.g. `text` or `numeric(10, 2)`
+    pub type_name: String,
+    pub not_null: bool,
+    pub options: FdwOption,
+}
+
+impl ImportSchema {
+    pub(crate) unsafe fn from_pg(stmt: *mut ImportForeignSchemaStmt, server_oid: Oid) -> Self {
+        let server = PgBox::<ForeignServer>::from_pg(GetForeignServer(server_oid));
+        let tables = PgList::<RangeVar>::from_pg((*stmt).table_list)
+            .iter_ptr()
+            .map(|table| string((*table).relname))
+            .collect();
+
+        let filter = match (*stmt).list_type {
+            list_type if list_type == ImportForeignSchemaType_FDW_IMPORT_SCHEMA_LIMIT_TO => {
+                ImportFilter::LimitTo(tables)
+            }
+            list_type if list_type == ImportForeignSchemaType_FDW_IMPORT_SCHEMA_EXCEPT => {
+                ImportFilter::Except(tables)
+            }
+            _ => ImportFilter::All,
+        };
+
+        ImportSchema {
+            server_name: string(server.servername),
+            server_opts: FdwOptions::from_pg_list(server.options),
+            user_opts: FdwOptions::user_mapping_options(GetUserId(), server_oid),
+            remote_schema: string((*stmt).remote_schema),
+            filter,
+            options: FdwOptions::from_pg_list((*stmt).options),
+        }
+    }
+}
+
+impl TableDefinition {
+    /// `CREATE FOREIGN TABLE` of the table on `server`. The table is created in the schema
+    /// `IMPORT FOREIGN SCHEMA` imports into.
+    pub fn to_sql(&self, server: &str) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let mut sql = format!("{} {}", quote_identifier(&column.name), column.type_name);
+
+                if !column.options.is_empty() {
+                    sql.push_str(&format!(" OPTIONS ({})", options_sql(&column.options)));
+                }
+
+                if column.not_null {
+                    sql.push_str(" NOT NULL");
+                }
+
+                sql
+            })
+            .collect();
+
+        let mut sql = format!(
+            "CREATE FOREIGN TABLE {} (\n  {}\n) SERVER {}",
+            quote_identifier(&self.name),
+            columns.join(",\n  "),
+            quote_identifier(server)
+        );
+
+        if !self.options.is_empty() {
+            sql.push_str(&format!("\nOPTIONS ({})", options_sql(&self.options)));
+        }
+
+        sql
+    }
+}
+
+// Sorted for a stable order of the options
+fn options_sql(options: &FdwOption) -> String {
+    let mut options: Vec<(&String, &String)> = options.iter().collect();
+
+    options.sort();
+    options
+        .into_iter()
+        .map(|(name, value)| format!("{} {}", quote_identifier(name), quote_literal(value)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn quote_identifier(ident: &str) -> String {
+    let ident = cstring(ident);
+
+    unsafe { string(pg_sys::quote_identifier(ident.as_ptr()) as *mut std::os::raw::c_char) }
+}
+
+fn quote_literal(literal: &str) -> String {
+    let literal = cstring(literal);
+
+    unsafe { string(pg_sys::quote_literal_cstr(literal.as_ptr())) }
+}
+
+unsafe fn string(s: *mut std::os::raw::c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
@@ -0,0 +1,123 @@
+use crate::qual::{operator_name, strip_relabel};
+use crate::{Collation, FdwOptions, Qual};
+use pg_sys::*;
+use pgx::*;
+
+/// A join of two foreign tables computed by `ForeignData::join`
+#[derive(Debug)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub outer: JoinSide,
+    pub inner: JoinSide,
+    /// Join conditions, implicitly AND:ed together. Empty for a cross join.
+    pub clauses: Vec<JoinClause>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+/// One of the joined tables
+#[derive(Debug)]
+pub struct JoinSide {
+    pub options: FdwOptions,
+    /// Restrictions of the table, applied before joining
+    pub quals: Vec<Qual>,
+    /// Columns of the table the query references, in table order
+    pub columns: Vec<String>,
+}
+
+/// `outer_column <operator> inner_column`, e.g. `a.id = b.a_id`
+#[derive(Debug, Clone)]
+pub struct JoinClause {
+    pub outer_column: String,
+    pub operator: String,
+    pub inner_column: String,
+    /// Collation the operator compares with, `None` for types that aren't collatable
+    pub collation: Option<Collation>,
+}
+
+impl JoinKind {
+    pub(crate) fn from_pg(jointype: JoinType) -> Option<Self> {
+        match jointype {
+            jointype if jointype == JoinType_JOIN_INNER => Some(JoinKind::Inner),
+            jointype if jointype == JoinType_JOIN_LEFT => Some(JoinKind::Left),
+            jointype if jointype == JoinType_JOIN_RIGHT => Some(JoinKind::Right),
+            jointype if jointype == JoinType_JOIN_FULL => Some(JoinKind::Full),
+            _ => None,
+        }
+    }
+}
+
+/// A side of the join during planning and execution
+pub(crate) struct JoinRel<'a> {
+    pub relid: Index,
+    pub desc: &'a PgTupleDesc<'a>,
+}
+
+/// Translates `clauses` (a list of `Expr`) comparing a column of `outer` with a column of
+/// `inner` into `JoinClause`s. `None` if any of them can't be translated.
+pub(crate) unsafe fn join_clauses(
+    clauses: *mut List,
+    outer: &JoinRel,
+    inner: &JoinRel,
+) -> Option<Vec<JoinClause>> {
+    PgList::<Expr>::from_pg(clauses)
+        .iter_ptr()
+        .map(|clause| join_clause(clause, outer, inner))
+        .collect()
+}
+
+unsafe fn join_clause(clause: *mut Expr, outer: &JoinRel, inner: &JoinRel) -> Option<JoinClause> {
+    if !is_a(clause as *mut Node, NodeTag_T_OpExpr) {
+        return None;
+    }
+
+    let op = clause as *mut OpExpr;
+    let args = PgList::<Expr>::from_pg((*op).args);
+
+    if args.len() != 2 {
+        return None;
+    }
+
+    let (left, right) = (
+        strip_relabel(args.get_ptr(0)?),
+        strip_relabel(args.get_ptr(1)?),
+    );
+
+    let (outer_column, opno, inner_column) = match (column(left, outer), column(right, inner)) {
+        (Some(outer_column), Some(inner_column)) => (outer_column, (*op).opno, inner_column),
+        _ => (
+            column(right, outer)?,
+            get_commutator((*op).opno),
+            column(left, inner)?,
+        ),
+    };
+
+    Some(JoinClause {
+        outer_column,
+        operator: operator_name(opno)?,
+        inner_column,
+        collation: Collation::from_oid((*op).inputcollid),
+    })
+}
+
+unsafe fn column(expr: *mut Expr, rel: &JoinRel) -> Option<String> {
+    if !is_a(expr as *mut Node, NodeTag_T_Var) {
+        return None;
+    }
+
+    let var = expr as *mut Var;
+
+    if (*var).varno != rel.relid || (*var).varlevelsup != 0 || (*var).varattno <= 0 {
+        return None;
+    }
+
+    rel.desc
+        .get((*var).varattno as usize - 1)
+        .map(|attr| attr.name().into())
+}
@@ -1,22 +1,70 @@
+use parallel::ParallelScan;
 use pg_sys::*;
 use pgx::*;
 use std::collections::HashMap;
 use std::ffi::CStr;
 
+mod aggregate;
+mod error;
+mod explain;
+mod import;
+mod join;
+mod limit;
+mod modify;
+mod options;
+mod parallel;
+mod qual;
+mod scan;
+mod sort;
+
+pub use aggregate::{Aggregate, Aggregation};
+pub use error::{FdwError, FdwResult};
+pub use explain::{Explain, Planned};
+pub use import::{ColumnDefinition, ImportFilter, ImportSchema, TableDefinition};
+pub use join::{Join, JoinClause, JoinKind, JoinSide};
+pub use limit::Limit;
+pub use modify::{Assignment, DirectModify, ModifyKind};
+pub use options::{FromOptions, OptionCatalog, OptionSpec, OptionType, OptionValue};
+pub use pgx_fdw_derive::FromOptions;
+pub use qual::{Collation, Qual, QualValue};
+pub use scan::Scan;
+pub use sort::SortKey;
+
 // https://www.postgresql.org/docs/13/fdw-callbacks.html
 pub type Tuple = (String, Option<pg_sys::Datum>, pgx::PgOid);
 pub type FdwOption = HashMap<String, String>;
+/// Options of the columns of a table, by column name
+pub type ColumnOptions = HashMap<String, FdwOption>;
+
+// Strings passed to Postgres can't have nul bytes
+fn cstring(s: &str) -> std::ffi::CString {
+    std::ffi::CString::new(s.replace('\0', ""))
+        .expect("string without nul bytes can't fail to convert")
+}
 
 #[derive(Debug)]
 pub struct FdwOptions {
+    /// Options of the foreign data wrapper, e.g. defaults of every server
+    pub wrapper_opts: FdwOption,
     pub server_opts: FdwOption,
+    /// Options of the user mapping of the current user, or else of the `PUBLIC` one. Empty
+    /// when neither exists.
+    pub user_opts: FdwOption,
     pub table_opts: FdwOption,
+    /// Options given with `ALTER FOREIGN TABLE ... ALTER COLUMN ... OPTIONS (...)`. Columns
+    /// without options have no entry.
+    pub column_opts: ColumnOptions,
     pub table_name: String,
     pub table_namespace: String,
 }
 
 impl FdwOptions {
     pub fn from_relation(relation: &PgRelation) -> Self {
+        Self::for_user(relation, unsafe { pg_sys::GetUserId() })
+    }
+
+    /// Options of `relation` with the user mapping of `userid`
+    pub fn for_user(relation: &PgRelation, userid: pg_sys::Oid) -> Self {
         let table = PgBox::<pg_sys::ForeignTable>::from_pg(unsafe {
             pg_sys::GetForeignTable(relation.rd_id)
         });
@@ -24,14 +72,69 @@ impl FdwOptions {
             pg_sys::GetForeignServer(table.serverid)
         });
 
+        let wrapper = PgBox::<pg_sys::ForeignDataWrapper>::from_pg(unsafe {
+            pg_sys::GetForeignDataWrapper(server.fdwid)
+        });
+
         Self {
+            wrapper_opts: Self::from_pg_list(wrapper.options),
             server_opts: Self::from_pg_list(server.options),
+            user_opts: Self::user_mapping_options(userid, server.serverid),
             table_opts: Self::from_pg_list(table.options),
+            column_opts: Self::column_options(relation),
             table_name: relation.name().into(),
             table_namespace: relation.namespace().into(),
         }
     }
 
+    fn user_mapping_options(userid: pg_sys::Oid, serverid: pg_sys::Oid) -> FdwOption {
+        let exists = |userid: pg_sys::Oid| unsafe {
+            let tuple = pg_sys::SearchSysCache2(
+                pg_sys::SysCacheIdentifier_USERMAPPINGUSERSERVER as i32,
+                userid as pg_sys::Datum,
+                serverid as pg_sys::Datum,
+            );
+
+            if tuple.is_null() {
+                return false;
+            }
+
+            pg_sys::ReleaseSysCache(tuple);
+            true
+        };
+
+        // `GetUserMapping` raises an error when there's no mapping at all. `PUBLIC` mappings
+        // have an invalid user.
+        if !exists(userid) && !exists(pg_sys::InvalidOid) {
+            return HashMap::new();
+        }
+
+        let mapping = PgBox::<pg_sys::UserMapping>::from_pg(unsafe {
+            pg_sys::GetUserMapping(userid, serverid)
+        });
+
+        Self::from_pg_list(mapping.options)
+    }
+
+    fn column_options(relation: &PgRelation) -> ColumnOptions {
+        relation
+            .tuple_desc()
+            .iter()
+            .filter(|attr| !attr.is_dropped())
+            .filter_map(|attr| {
+                let options = Self::from_pg_list(unsafe {
+                    pg_sys::GetForeignColumnOptions(relation.rd_id, attr.attnum)
+                });
+
+                if options.is_empty() {
+                    return None;
+                }
+
+                Some((attr.name().into(), options))
+            })
+            .collect()
+    }
+
     fn from_pg_list(opts: *mut pg_sys::List) -> FdwOption {
         if opts.is_null() {
             return HashMap::new();
@@ -41,19 +144,50 @@ impl FdwOptions {
 
         pg_list
             .iter_ptr()
-            .map(|ptr| unsafe { Self::elem_to_tuple(ptr) })
-            .collect::<FdwOption>()
+            .filter_map(|ptr| unsafe { Self::elem_to_tuple(ptr) }.transpose())
+            .collect::<FdwResult<FdwOption>>()
+            .unwrap_or_else(|err| err.report())
     }
 
-    unsafe fn elem_to_tuple(elem: *mut pg_sys::DefElem) -> (String, String) {
-        let key = (*elem).defname;
-        let value = (*((*elem).arg as *mut pg_sys::Value)).val.str_;
+    /// The name and value of an option, `None` for options dropped by `ALTER ... OPTIONS
+    /// (DROP ...)`
+    unsafe fn elem_to_tuple(elem: *mut pg_sys::DefElem) -> FdwResult<Option<(String, String)>> {
+        if (*elem).defname.is_null() {
+            return Err(FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_OPTION_NAME,
+                "option without a name",
+            ));
+        }
 
-        match (CStr::from_ptr(key).to_str(), CStr::from_ptr(value).to_str()) {
-            (Ok(k), Ok(v)) => (k.into(), v.into()),
-            (Err(err), _) => error!("Option list key err {}", err),
-            (_, Err(err)) => error!("Option list value err {}", err),
+        let key = CStr::from_ptr((*elem).defname).to_str().map_err(|err| {
+            FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_OPTION_NAME,
+                "option name isn't valid UTF-8",
+            )
+            .with_detail(format!("{}", err))
+        })?;
+
+        if (*elem).defaction == pg_sys::DefElemAction_DEFELEM_DROP {
+            return Ok(None);
         }
+
+        // An option without a value is a boolean flag, like `defGetBoolean` treats it.
+        // Otherwise `defGetString` handles strings, numbers, type names and identifier lists.
+        let value = if (*elem).arg.is_null() {
+            "true"
+        } else {
+            CStr::from_ptr(pg_sys::defGetString(elem))
+                .to_str()
+                .map_err(|err| {
+                    FdwError::new(
+                        PgSqlErrorCode::ERRCODE_FDW_INVALID_STRING_FORMAT,
+                        format!("value of option \"{}\" isn't valid UTF-8", key),
+                    )
+                    .with_detail(format!("{}", err))
+                })?
+        };
+
+        Ok(Some((key.into(), value.into())))
     }
 }
 
@@ -61,132 +195,1633 @@ pub trait ForeignData {
     type Item: IntoDatum;
     type RowIterator: Iterator<Item = Vec<Self::Item>>;
 
-    fn begin(options: &FdwOptions) -> Self;
-    fn execute(&mut self, desc: &PgTupleDesc) -> Self::RowIterator;
+    /// Errors returned by the methods of `ForeignData` are raised as a Postgres `ERROR`,
+    /// aborting the current transaction.
+    ///
+    /// `options.user_opts` are those of the user the table is accessed as, which is the
+    /// owner of a view when the table is queried through one.
+    fn begin(options: &FdwOptions) -> FdwResult<Self>
+    where
+        Self: Sized;
+    /// Produce the rows of `scan`. Rows may either contain a value for every column of
+    /// `scan.desc` or only for `scan.columns`, in which case the remaining columns are NULL.
+    fn execute(&mut self, scan: &Scan) -> FdwResult<Self::RowIterator>;
+    /// Options accepted by the validator created with `FdwState::validate`, in addition to
+    /// the ones of pgx-fdw itself. Defaults of options that aren't given are filled into the
+    /// `FdwOptions` passed to the other methods.
+    fn option_schema() -> Vec<OptionSpec> {
+        vec![]
+    }
+
+    /// Columns identifying a row. Used as the key of updates and deletes, and for
+    /// parameterized scans looking up the rows matching each row of a nested loop join when
+    /// `supports_qual` accepts `=` on them.
     fn indices(_options: &FdwOptions) -> Option<Vec<String>> {
         None
     }
 
-    fn insert(&self, _desc: &PgTupleDesc, _row: Vec<Tuple>) -> Option<Vec<Tuple>> {
+    /// Restart a scan produced by `execute`, e.g. for every row of the outer side of a nested
+    /// loop. The quals of `scan` have the parameter values of the new scan. When `None` the
+    /// scan is executed again.
+    fn rescan(&mut self, _scan: &Scan) -> FdwResult<Option<Self::RowIterator>> {
+        Ok(None)
+    }
+
+    /// Whether `execute` fully enforces `qual` itself. Quals that aren't supported are
+    /// left for Postgres to check on the returned rows.
+    fn supports_qual(_options: &FdwOptions, _qual: &Qual) -> bool {
+        false
+    }
+
+    /// Whether `execute` can return rows ordered by `key`. A query's `ORDER BY` is only pushed
+    /// down when every key is supported.
+    fn supports_sort(_options: &FdwOptions, _key: &SortKey) -> bool {
+        false
+    }
+
+    /// Whether `execute` can apply a constant `LIMIT`/`OFFSET` itself. It's only pushed down
+    /// for single table queries where every qual and the `ORDER BY` is pushed down as well.
+    fn supports_limit(_options: &FdwOptions) -> bool {
+        false
+    }
+
+    /// Whether `aggregate` can compute `aggregation`. It's only pushed down for single table
+    /// queries where every qual is pushed down as well.
+    fn supports_aggregation(_options: &FdwOptions, _aggregation: &Aggregation) -> bool {
+        false
+    }
+
+    /// Produce the grouped rows of a query, used instead of `execute` when
+    /// `supports_aggregation` accepted `aggregation`. Each row has a value for every column of
+    /// `aggregation.group_by` followed by one for every aggregate.
+    fn aggregate(
+        &mut self,
+        _desc: &PgTupleDesc,
+        _quals: &[Qual],
+        _aggregation: &Aggregation,
+    ) -> FdwResult<Self::RowIterator> {
+        Err(FdwError::new(
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "ForeignData::aggregate isn't implemented, it's required for aggregation pushdown",
+        ))
+    }
+
+    /// Whether `join` can compute `join`. It's only asked for joins of two tables of the same
+    /// server where every qual of both tables is pushed down.
+    fn supports_join(_join: &Join) -> bool {
+        false
+    }
+
+    /// Produce the rows of a join, used instead of `execute` when `supports_join` accepted
+    /// `join`. `self` is created by `begin` with the options of the outer table. Each row has
+    /// a value for every column of `join.outer.columns` followed by one for every column of
+    /// `join.inner.columns`, NULL for the missing side of an outer join.
+    fn join(&mut self, _join: &Join) -> FdwResult<Self::RowIterator> {
+        Err(FdwError::new(
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "ForeignData::join isn't implemented, it's required for join pushdown",
+        ))
+    }
+
+    /// About `target_rows` randomly chosen rows of the table for `ANALYZE`, along with the
+    /// total number of rows. When `None` the rows are sampled from a scan of the whole table
+    /// with `execute`.
+    fn sample(
+        &mut self,
+        _desc: &PgTupleDesc,
+        _target_rows: usize,
+    ) -> FdwResult<Option<(Self::RowIterator, f64)>> {
+        Ok(None)
+    }
+
+    /// Tables of `import.remote_schema` to create with `IMPORT FOREIGN SCHEMA`
+    fn import_schema(_import: &ImportSchema) -> FdwResult<Vec<TableDefinition>> {
+        Err(FdwError::new(
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "IMPORT FOREIGN SCHEMA isn't supported",
+        ))
+    }
+
+    /// Whether scans may run in parallel workers. Each worker creates its own `Self` with
+    /// `begin`.
+    fn parallel_safe(_options: &FdwOptions) -> bool {
+        false
+    }
+
+    /// Number of partitions, e.g. shards or chunks of a file, to split a scan into for
+    /// parallel query. Workers claim partitions one at a time and scan each with
+    /// `execute_partition`. `None` for scans that can't be split. Requires `parallel_safe`.
+    fn parallel_partitions(_options: &FdwOptions) -> Option<usize> {
+        None
+    }
+
+    /// Produce the rows of partition `partition` of a parallel scan. Each row has a value for
+    /// every column of `scan.desc`, or only for `scan.columns` as with `execute`. Parallel
+    /// scans aren't sorted or limited.
+    fn execute_partition(
+        &mut self,
+        _scan: &Scan,
+        _partition: usize,
+    ) -> FdwResult<Self::RowIterator> {
+        Err(FdwError::new(
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "ForeignData::execute_partition isn't implemented, it's required for partitioned parallel scans",
+        ))
+    }
+
+    /// Estimated number of rows `execute` returns for `quals`. When `None` the `rows` table
+    /// option, or else the row count of the last `ANALYZE`, scaled by Postgres' selectivity
+    /// estimate of `quals` is used.
+    fn estimate_rows(_options: &FdwOptions, _quals: &[Qual]) -> Option<f64> {
+        None
+    }
+
+    /// Estimated average width in bytes of a row. When `None` Postgres derives it from the
+    /// column types.
+    fn estimate_width(_options: &FdwOptions) -> Option<i32> {
         None
     }
 
+    /// Estimated `(startup_cost, total_cost)` of fetching `rows` rows matching `quals`.
+    fn estimate_cost(_options: &FdwOptions, _quals: &[Qual], _rows: f64) -> Option<(f64, f64)> {
+        None
+    }
+
+    /// Add properties to the `EXPLAIN` output of the scan or modification `self` was created
+    /// for by `begin`, e.g. the request `planned` is sent to the source as. With
+    /// `EXPLAIN ANALYZE` this is called after the statement ran.
+    fn explain(&self, _planned: &Planned, _explain: &mut Explain) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Called when a scan is finished, before `self` is dropped along with the executor's
+    /// memory. Errors drop `self` without calling this.
+    fn end_scan(&mut self) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Called when an insert, update or delete is finished, before `self` is dropped along
+    /// with the executor's memory. Errors drop `self` without calling this.
+    fn end_modify(&mut self) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Insert `row`. The returned columns, e.g. generated ids, replace the inserted values in
+    /// what `RETURNING` reports.
+    fn insert(
+        &self,
+        _desc: &PgTupleDesc,
+        _column_opts: &ColumnOptions,
+        _row: Vec<Tuple>,
+    ) -> FdwResult<Option<Vec<Tuple>>> {
+        Ok(None)
+    }
+
+    /// Insert `rows`, buffered up to the `batch_size` table or server option. Used instead of
+    /// `insert` when `batch_size` is above 1 and the inserted rows aren't needed right away,
+    /// i.e. without `RETURNING`, `WITH CHECK OPTION` or `AFTER` triggers. The last batch
+    /// is inserted when the statement ends. Inserts each row with `insert` by default.
+    fn insert_batch(
+        &self,
+        desc: &PgTupleDesc,
+        column_opts: &ColumnOptions,
+        rows: Vec<Vec<Tuple>>,
+    ) -> FdwResult<()> {
+        for row in rows {
+            self.insert(desc, column_opts, row)?;
+        }
+
+        Ok(())
+    }
+
+    /// Update the row identified by `indices` with `row`. The returned columns replace the
+    /// updated values in what `RETURNING` reports.
     fn update(
         &self,
         _desc: &PgTupleDesc,
+        _column_opts: &ColumnOptions,
         _row: Vec<Tuple>,
         _indices: Vec<Tuple>,
-    ) -> Option<Vec<Tuple>> {
-        None
+    ) -> FdwResult<Option<Vec<Tuple>>> {
+        Ok(None)
     }
 
-    fn delete(&self, _desc: &PgTupleDesc, _indices: Vec<Tuple>) -> Option<Vec<Tuple>> {
-        None
+    /// Delete the row identified by `indices`. The returned columns, usually the deleted row,
+    /// are what `RETURNING` reports.
+    fn delete(
+        &self,
+        _desc: &PgTupleDesc,
+        _column_opts: &ColumnOptions,
+        _indices: Vec<Tuple>,
+    ) -> FdwResult<Option<Vec<Tuple>>> {
+        Ok(None)
+    }
+
+    /// Whether `modify` can execute `modify` in a single operation. It's only asked for
+    /// statements on a single table where every qual is pushed down, and `QualValue::Param`s
+    /// are `None`. Otherwise the rows are fetched with `execute` and modified one at a time.
+    fn supports_direct_modify(_options: &FdwOptions, _modify: &DirectModify) -> bool {
+        false
+    }
+
+    /// Execute an `UPDATE` or `DELETE` accepted by `supports_direct_modify`, returning the
+    /// number of modified rows. When `modify.returning` the modified rows are returned as well,
+    /// with a value for every column of `desc`, the new values for an update.
+    fn modify(
+        &mut self,
+        _desc: &PgTupleDesc,
+        _column_opts: &ColumnOptions,
+        _modify: &DirectModify,
+    ) -> FdwResult<(u64, Option<Self::RowIterator>)> {
+        Err(FdwError::new(
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "ForeignData::modify isn't implemented, it's required for direct modification",
+        ))
     }
 }
 
+const DEFAULT_ROWS: f64 = 1000.0;
+const DEFAULT_STARTUP_COST: f64 = 10.0;
+// Sorting remotely isn't free, but should win over a local sort
+const SORT_COST_MULTIPLIER: f64 = 1.2;
+
+// Stored in `RelOptInfo.fdw_private` between the planner callbacks
+struct FdwPlanState {
+    rows: f64,
+    startup_cost: pg_sys::Cost,
+    total_cost: pg_sys::Cost,
+    has_local_conds: bool,
+    // Quals not referencing the relation, e.g. `WHERE $1 > 0`. Postgres only checks them
+    // with a gating `Result` above scans of base relations, so they prevent pushing down an
+    // aggregate or limit.
+    has_pseudoconstant: bool,
+}
+
+// Layout of `ForeignScan.fdw_private`
+const FDW_PRIVATE_RELID: usize = 0;
+const FDW_PRIVATE_TABLE_OID: usize = 1;
+const FDW_PRIVATE_REMOTE_CONDS: usize = 2;
+const FDW_PRIVATE_ATTNUMS: usize = 3;
+const FDW_PRIVATE_SORT: usize = 4;
+const FDW_PRIVATE_LIMIT: usize = 5;
+const FDW_PRIVATE_AGGREGATION: usize = 6;
+const FDW_PRIVATE_JOIN: usize = 7;
+const FDW_PRIVATE_MODIFY: usize = 8;
+
+// Layout of the `FDW_PRIVATE_JOIN` list, describing the inner table of a join
+const FDW_JOIN_PRIVATE_RELID: usize = 0;
+const FDW_JOIN_PRIVATE_TABLE_OID: usize = 1;
+const FDW_JOIN_PRIVATE_REMOTE_CONDS: usize = 2;
+const FDW_JOIN_PRIVATE_ATTNUMS: usize = 3;
+const FDW_JOIN_PRIVATE_JOINTYPE: usize = 4;
+const FDW_JOIN_PRIVATE_CLAUSES: usize = 5;
+
+// Layout of the `FDW_PRIVATE_MODIFY` list of a direct modification
+const FDW_MODIFY_PRIVATE_ATTNUMS: usize = 0;
+const FDW_MODIFY_PRIVATE_ASSIGNMENTS: usize = 1;
+const FDW_MODIFY_PRIVATE_RETURNING: usize = 2;
+const FDW_MODIFY_PRIVATE_SET_PROCESSED: usize = 3;
+
+// Layout of `ForeignPath.fdw_private` for paths of upper relations
+const FDW_PATH_PRIVATE_RELID: usize = 0;
+const FDW_PATH_PRIVATE_LIMIT: usize = 1;
+const FDW_PATH_PRIVATE_AGGREGATION: usize = 2;
+
+// Layout of `ForeignPath.fdw_private` for paths of join relations
+const FDW_JOIN_PATH_PRIVATE_OUTER_RELID: usize = 0;
+const FDW_JOIN_PATH_PRIVATE_INNER_RELID: usize = 1;
+const FDW_JOIN_PATH_PRIVATE_JOINTYPE: usize = 2;
+const FDW_JOIN_PATH_PRIVATE_CLAUSES: usize = 3;
+
 #[derive(Debug)]
 pub struct FdwState<T: ForeignData> {
     state: T,
     itr: *mut T::RowIterator,
+    relation: Relation,
+    fdw_private: *mut List,
+    param_exprs: *mut List,
+    rescanned: bool,
+    column_opts: ColumnOptions,
+    /// Partitions of a parallel scan, 0 for other scans
+    partitions: usize,
+    /// Next partition scanned when there's no `parallel` state, i.e. no workers
+    next_partition: usize,
+    parallel: *mut ParallelScan,
+    /// Whether `ForeignData::modify` ran, for direct modifications
+    modified: bool,
+    /// Rows inserted at once with `ForeignData::insert_batch`, 1 when inserting row by row
+    batch_size: usize,
+    /// Inserted rows not yet handed to `ForeignData::insert_batch`
+    batch: Vec<HeapTuple>,
+}
+
+impl<T: ForeignData> Drop for FdwState<T> {
+    fn drop(&mut self) {
+        self.drop_iterator();
+    }
 }
 
 impl<T: ForeignData> FdwState<T> {
+    fn options(rel: &PgRelation) -> FdwOptions {
+        Self::with_defaults(FdwOptions::from_relation(rel))
+    }
+
+    fn with_defaults(mut options: FdwOptions) -> FdwOptions {
+        options::apply_defaults(&mut options, &T::option_schema());
+        options
+    }
+
+    /// The user the executor checks permissions of range table entry `rti` as, the owner
+    /// of a view with a foreign table or else the current user
+    unsafe fn check_as_user(estate: *mut EState, rti: Index) -> Oid {
+        let rte =
+            PgList::<RangeTblEntry>::from_pg((*estate).es_range_table).get_ptr(rti as usize - 1);
+
+        match rte {
+            Some(rte) if (*rte).checkAsUser != pg_sys::InvalidOid => (*rte).checkAsUser,
+            _ => pg_sys::GetUserId(),
+        }
+    }
+
+    /// The next partition of a parallel scan to scan, claimed from the other processes
+    fn next_partition(&mut self) -> Option<usize> {
+        if !self.parallel.is_null() {
+            return unsafe { (*self.parallel).claim() };
+        }
+
+        let partition = self.next_partition;
+
+        self.next_partition += 1;
+        Some(partition).filter(|partition| *partition < self.partitions)
+    }
+
+    fn drop_iterator(&mut self) {
+        if !self.itr.is_null() {
+            unsafe { drop(Box::from_raw(self.itr)) };
+            self.itr = std::ptr::null_mut();
+        }
+    }
+
+    /// Inserts the buffered rows with `ForeignData::insert_batch`
+    fn flush_batch(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+
+        let tupdesc = unsafe { PgTupleDesc::from_pg_copy((*self.relation).rd_att) };
+        let natts = tupdesc.len();
+        let tuples = std::mem::take(&mut self.batch);
+        // The values point into the tuples, which are freed once the rows are inserted
+        let rows = tuples
+            .iter()
+            .map(|tuple| {
+                let mut datums = vec![0 as pg_sys::Datum; natts];
+                let mut nulls = vec![true; natts];
+
+                unsafe {
+                    pg_sys::heap_deform_tuple(
+                        *tuple,
+                        tupdesc.as_ptr(),
+                        datums.as_mut_ptr(),
+                        nulls.as_mut_ptr(),
+                    );
+                }
+
+                Self::values_to_tuples(&tupdesc, &datums, &nulls)
+            })
+            .collect();
+
+        self.state
+            .insert_batch(&tupdesc, &self.column_opts, rows)
+            .unwrap_or_else(|err| err.report());
+
+        tuples
+            .into_iter()
+            .for_each(|tuple| unsafe { pg_sys::heap_freetuple(tuple) });
+    }
+
+    /// `batch_size` of an insert into `rinfo`. Rows are only buffered when the inserted row
+    /// isn't needed right after `ExecForeignInsert`. The last batch is inserted after the
+    /// `AFTER` triggers of the statement ran, so they also disable batching.
+    unsafe fn batch_size(opts: &FdwOptions, rinfo: *mut ResultRelInfo) -> usize {
+        let trigdesc = (*rinfo).ri_TrigDesc;
+        let after_triggers = !trigdesc.is_null()
+            && ((*trigdesc).trig_insert_after_row
+                || (*trigdesc).trig_insert_after_statement
+                || (*trigdesc).trig_insert_new_table);
+
+        if !(*rinfo).ri_projectReturning.is_null()
+            || !(*rinfo).ri_WithCheckOptions.is_null()
+            || after_triggers
+        {
+            return 1;
+        }
+
+        opts.get::<std::num::NonZeroUsize>("batch_size")
+            .unwrap_or_else(|err| err.report())
+            .map_or(1, std::num::NonZeroUsize::get)
+    }
+
     unsafe extern "C" fn get_foreign_rel_size(
-        _root: *mut PlannerInfo,
+        root: *mut PlannerInfo,
         baserel: *mut RelOptInfo,
-        _foreigntableid: Oid,
+        foreigntableid: Oid,
     ) {
-        (*baserel).rows = 0.0;
+        pg_sys::guard::guard(move || {
+            let rel = PgRelation::with_lock(foreigntableid, pg_sys::NoLock as pg_sys::LOCKMODE);
+            let opts = Self::options(&rel);
+            let tupdesc = rel.tuple_desc();
+
+            let clauses = qual::classify_clauses(
+                (*baserel).relid,
+                &tupdesc,
+                (*baserel).baserestrictinfo,
+                |qual| T::supports_qual(&opts, qual),
+            );
+
+            // Rows returned by the source, before Postgres applies the local conditions
+            let fetched_rows = T::estimate_rows(&opts, &clauses.quals).unwrap_or_else(|| {
+                let rows = opts
+                    .get::<f64>("rows")
+                    .unwrap_or_else(|err| err.report())
+                    // Row count of the last `ANALYZE`
+                    .or_else(|| Some((*baserel).tuples).filter(|tuples| *tuples > 0.0))
+                    .unwrap_or(DEFAULT_ROWS);
+
+                rows * Self::selectivity(root, clauses.remote_conds)
+            });
+            let rows =
+                pg_sys::clamp_row_est(fetched_rows * Self::selectivity(root, clauses.local_conds));
+
+            if let Some(width) = T::estimate_width(&opts) {
+                (*(*baserel).reltarget).width = width;
+            }
+
+            let (startup_cost, total_cost) = T::estimate_cost(&opts, &clauses.quals, fetched_rows)
+                .unwrap_or_else(|| {
+                    let mut local_cost = pg_sys::QualCost::default();
+                    pg_sys::cost_qual_eval(&mut local_cost, clauses.local_conds, root);
+
+                    let startup_cost = DEFAULT_STARTUP_COST + local_cost.startup;
+                    let run_cost = fetched_rows * (pg_sys::cpu_tuple_cost + local_cost.per_tuple);
+
+                    (startup_cost, startup_cost + run_cost)
+                });
+
+            (*baserel).rows = rows;
+            (*baserel).fdw_private =
+                PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(FdwPlanState {
+                    rows,
+                    startup_cost,
+                    total_cost,
+                    has_local_conds: !clauses.local_conds.is_null(),
+                    has_pseudoconstant: PgList::<RestrictInfo>::from_pg(
+                        (*baserel).baserestrictinfo,
+                    )
+                    .iter_ptr()
+                    .any(|rinfo| (*rinfo).pseudoconstant),
+                }) as pgx::memcxt::void_mut_ptr;
+        })
+    }
+
+    unsafe fn selectivity(root: *mut PlannerInfo, clauses: *mut List) -> f64 {
+        pg_sys::clauselist_selectivity(
+            root,
+            clauses,
+            0,
+            pg_sys::JoinType_JOIN_INNER,
+            std::ptr::null_mut(),
+        )
     }
 
     unsafe extern "C" fn get_foreign_paths(
         root: *mut PlannerInfo,
         baserel: *mut RelOptInfo,
-        _foreigntableid: Oid,
+        foreigntableid: Oid,
     ) {
-        pg_sys::add_path(
+        pg_sys::guard::guard(move || {
+            let plan_state =
+                PgBox::<FdwPlanState>::from_pg((*baserel).fdw_private as *mut FdwPlanState);
+            let rel = PgRelation::with_lock(foreigntableid, pg_sys::NoLock as pg_sys::LOCKMODE);
+            let opts = Self::options(&rel);
+            let tupdesc = rel.tuple_desc();
+
+            let query_pathkeys = (*root).query_pathkeys;
+            let sortable = match sort::from_pathkeys(query_pathkeys, (*baserel).relid, &tupdesc) {
+                Some(keys) => {
+                    !keys.is_empty() && keys.iter().all(|(_, key)| T::supports_sort(&opts, key))
+                }
+                None => false,
+            };
+
+            if sortable {
+                pg_sys::add_path(
+                    baserel,
+                    pg_sys::create_foreignscan_path(
+                        root,
+                        baserel,
+                        std::ptr::null_mut(),
+                        plan_state.rows,
+                        plan_state.startup_cost * SORT_COST_MULTIPLIER,
+                        plan_state.total_cost * SORT_COST_MULTIPLIER,
+                        query_pathkeys,
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    ) as *mut pg_sys::Path,
+                );
+            }
+
+            pg_sys::add_path(
+                baserel,
+                pg_sys::create_foreignscan_path(
+                    root,
+                    baserel,
+                    std::ptr::null_mut(),
+                    plan_state.rows,
+                    plan_state.startup_cost,
+                    plan_state.total_cost,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                ) as *mut pg_sys::Path,
+            );
+
+            Self::add_parameterized_paths(root, baserel, &opts, &tupdesc, &plan_state);
+            Self::add_partial_path(root, baserel, &opts, &plan_state);
+        })
+    }
+
+    /// Adds a parallel aware path scanning the partitions of `ForeignData::parallel_partitions`
+    /// in parallel workers, which Postgres gathers
+    unsafe fn add_partial_path(
+        root: *mut PlannerInfo,
+        baserel: *mut RelOptInfo,
+        opts: &FdwOptions,
+        plan_state: &PgBox<FdwPlanState>,
+    ) {
+        // `consider_parallel` is only set when `is_foreign_scan_parallel_safe` is
+        if !(*baserel).consider_parallel || !(*baserel).lateral_relids.is_null() {
+            return;
+        }
+
+        let partitions = match T::parallel_partitions(opts) {
+            Some(partitions) if partitions > 1 => partitions,
+            _ => return,
+        };
+        let workers = partitions.min(pg_sys::max_parallel_workers_per_gather.max(0) as usize);
+
+        if workers == 0 {
+            return;
+        }
+
+        let divisor = parallel::parallel_divisor(workers);
+        let path = pg_sys::create_foreignscan_path(
+            root,
             baserel,
-            pg_sys::create_foreignscan_path(
-                root,
+            std::ptr::null_mut(),
+            pg_sys::clamp_row_est(plan_state.rows / divisor),
+            plan_state.startup_cost,
+            plan_state.startup_cost + (plan_state.total_cost - plan_state.startup_cost) / divisor,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+
+        (*path).path.parallel_aware = true;
+        (*path).path.parallel_workers = workers as i32;
+
+        pg_sys::add_partial_path(baserel, path as *mut pg_sys::Path);
+    }
+
+    /// Adds a path for every join clause comparing one of `ForeignData::indices` with a
+    /// column of another relation, for use as the inner side of a nested loop
+    unsafe fn add_parameterized_paths(
+        root: *mut PlannerInfo,
+        baserel: *mut RelOptInfo,
+        opts: &FdwOptions,
+        tupdesc: &PgTupleDesc,
+        plan_state: &PgBox<FdwPlanState>,
+    ) {
+        let indices = match T::indices(opts) {
+            Some(indices) if !indices.is_empty() => indices,
+            _ => return,
+        };
+
+        let relid = (*baserel).relid;
+        let mut clauses = PgList::<RestrictInfo>::from_pg((*baserel).joininfo)
+            .iter_ptr()
+            .filter(|rinfo| pg_sys::join_clause_is_movable_to(*rinfo, baserel))
+            .collect::<Vec<_>>();
+
+        // Equality joins are usually only represented by equivalence classes
+        if (*baserel).has_eclass_joins {
+            for (i, attr) in tupdesc.iter().enumerate() {
+                if !indices.iter().any(|index| index == attr.name()) {
+                    continue;
+                }
+
+                let mut attnum = (i + 1) as AttrNumber;
+                let ec_clauses = pg_sys::generate_implied_equalities_for_column(
+                    root,
+                    baserel,
+                    Some(Self::ec_member_matches_column),
+                    &mut attnum as *mut AttrNumber as pgx::memcxt::void_mut_ptr,
+                    (*baserel).lateral_referencers,
+                );
+
+                clauses.extend(PgList::<RestrictInfo>::from_pg(ec_clauses).iter_ptr());
+            }
+        }
+
+        for rinfo in clauses {
+            let rinfos = pg_sys::lappend(std::ptr::null_mut(), rinfo as pgx::memcxt::void_mut_ptr);
+            let lookup = qual::classify_clauses(relid, tupdesc, rinfos, |qual| match qual {
+                Qual::Op {
+                    column, operator, ..
+                } => operator == "=" && indices.contains(column) && T::supports_qual(opts, qual),
+                _ => false,
+            });
+
+            if lookup.quals.is_empty() {
+                continue;
+            }
+
+            let required_outer = pg_sys::bms_union(
+                pg_sys::bms_difference((*rinfo).clause_relids, (*baserel).relids),
+                (*baserel).lateral_relids,
+            );
+
+            if pg_sys::bms_is_empty(required_outer) {
+                continue;
+            }
+
+            // Only the rows matching the looked up value are fetched
+            let rows = pg_sys::clamp_row_est(plan_state.rows * Self::selectivity(root, rinfos));
+            let total_cost = plan_state.startup_cost + rows * pg_sys::cpu_tuple_cost;
+
+            pg_sys::add_path(
                 baserel,
-                std::ptr::null_mut(),
-                (*baserel).rows,
-                pg_sys::Cost::from(10),
-                pg_sys::Cost::from(0),
-                std::ptr::null_mut(),
+                pg_sys::create_foreignscan_path(
+                    root,
+                    baserel,
+                    std::ptr::null_mut(),
+                    rows,
+                    plan_state.startup_cost,
+                    total_cost,
+                    std::ptr::null_mut(),
+                    required_outer,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                ) as *mut pg_sys::Path,
+            );
+        }
+    }
+
+    /// `generate_implied_equalities_for_column` callback matching the column `arg` points to
+    unsafe extern "C" fn ec_member_matches_column(
+        _root: *mut PlannerInfo,
+        rel: *mut RelOptInfo,
+        _ec: *mut EquivalenceClass,
+        em: *mut EquivalenceMember,
+        arg: *mut ::std::os::raw::c_void,
+    ) -> bool {
+        pg_sys::guard::guard(move || {
+            let expr = (*em).em_expr as *mut Node;
+
+            is_a(expr, pg_sys::NodeTag_T_Var)
+                && (*(expr as *mut Var)).varno == (*rel).relid
+                && (*(expr as *mut Var)).varlevelsup == 0
+                && (*(expr as *mut Var)).varattno == *(arg as *mut AttrNumber)
+        })
+    }
+
+    unsafe extern "C" fn get_foreign_join_paths(
+        root: *mut PlannerInfo,
+        joinrel: *mut RelOptInfo,
+        outerrel: *mut RelOptInfo,
+        innerrel: *mut RelOptInfo,
+        jointype: JoinType,
+        extra: *mut JoinPathExtraData,
+    ) {
+        pg_sys::guard::guard(move || {
+            let parse = (*root).parse;
+
+            // Called for every way of joining the same relations, the first one that can be
+            // pushed down is used
+            if !(*joinrel).fdw_private.is_null()
+                || (*parse).commandType != pg_sys::CmdType_CMD_SELECT
+                || !(*parse).rowMarks.is_null()
+            {
+                return;
+            }
+
+            let kind = match JoinKind::from_pg(jointype) {
+                Some(kind) => kind,
+                None => return,
+            };
+            let (outer_state, inner_state) = match (
+                Self::base_plan_state(outerrel),
+                Self::base_plan_state(innerrel),
+            ) {
+                (Some(outer_state), Some(inner_state)) => (outer_state, inner_state),
+                _ => return,
+            };
+
+            // Clauses filtering the result of an outer join can't be used as join conditions
+            let mut clauses = PgList::<Expr>::new();
+
+            for rinfo in PgList::<RestrictInfo>::from_pg((*extra).restrictlist).iter_ptr() {
+                if (*rinfo).pseudoconstant || (kind != JoinKind::Inner && (*rinfo).is_pushed_down) {
+                    return;
+                }
+
+                clauses.push((*rinfo).clause);
+            }
+
+            let clauses = clauses.into_pg();
+
+            // Whole-row and system column references can't be satisfied without a scanned relation
+            let target_vars = pg_sys::pull_var_clause(
+                (*(*joinrel).reltarget).exprs as *mut Node,
+                pg_sys::PVC_RECURSE_PLACEHOLDERS as i32,
+            );
+            let plain_columns =
+                PgList::<pg_sys::Node>::from_pg(target_vars)
+                    .iter_ptr()
+                    .all(|node| {
+                        is_a(node, pg_sys::NodeTag_T_Var) && (*(node as *mut Var)).varattno > 0
+                    });
+
+            if !plain_columns {
+                return;
+            }
+
+            let (outer_relid, inner_relid) = ((*outerrel).relid, (*innerrel).relid);
+            let outer_rel = Self::planned_relation(root, outer_relid);
+            let inner_rel = Self::planned_relation(root, inner_relid);
+            let (outer_desc, inner_desc) = (outer_rel.tuple_desc(), inner_rel.tuple_desc());
+            let join_clauses = join::join_clauses(
+                clauses,
+                &join::JoinRel {
+                    relid: outer_relid,
+                    desc: &outer_desc,
+                },
+                &join::JoinRel {
+                    relid: inner_relid,
+                    desc: &inner_desc,
+                },
+            );
+
+            let join_clauses = match join_clauses {
+                Some(join_clauses) => join_clauses,
+                None => return,
+            };
+
+            let join = Join {
+                kind,
+                outer: Self::planned_join_side(outerrel, &outer_rel, &outer_desc, target_vars),
+                inner: Self::planned_join_side(innerrel, &inner_rel, &inner_desc, target_vars),
+                clauses: join_clauses,
+            };
+
+            if !T::supports_join(&join) {
+                return;
+            }
+
+            // Both tables are still read remotely, but the rows are only returned joined
+            let rows = (*joinrel).rows;
+            let startup_cost = outer_state.total_cost + inner_state.total_cost;
+            let total_cost = startup_cost + rows * pg_sys::cpu_tuple_cost;
+
+            (*joinrel).fdw_private =
+                PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(FdwPlanState {
+                    rows,
+                    startup_cost,
+                    total_cost,
+                    has_local_conds: false,
+                    has_pseudoconstant: false,
+                }) as pgx::memcxt::void_mut_ptr;
+
+            let mut fdw_private = PgList::<std::os::raw::c_void>::new();
+            fdw_private.push(pg_sys::makeInteger(outer_relid as _) as pgx::memcxt::void_mut_ptr);
+            fdw_private.push(pg_sys::makeInteger(inner_relid as _) as pgx::memcxt::void_mut_ptr);
+            fdw_private.push(pg_sys::makeInteger(jointype as _) as pgx::memcxt::void_mut_ptr);
+            fdw_private.push(clauses as pgx::memcxt::void_mut_ptr);
+
+            pg_sys::add_path(
+                joinrel,
+                pg_sys::create_foreign_join_path(
+                    root,
+                    joinrel,
+                    std::ptr::null_mut(),
+                    rows,
+                    startup_cost,
+                    total_cost,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    fdw_private.into_pg(),
+                ) as *mut pg_sys::Path,
+            );
+        })
+    }
+
+    /// Planner state of a foreign table whose quals are all pushed down
+    unsafe fn base_plan_state(rel: *mut RelOptInfo) -> Option<PgBox<FdwPlanState>> {
+        if (*rel).reloptkind != pg_sys::RelOptKind_RELOPT_BASEREL || (*rel).fdw_private.is_null() {
+            return None;
+        }
+
+        let plan_state = PgBox::<FdwPlanState>::from_pg((*rel).fdw_private as *mut FdwPlanState);
+
+        if plan_state.has_local_conds {
+            None
+        } else {
+            Some(plan_state)
+        }
+    }
+
+    unsafe fn planned_relation(root: *mut PlannerInfo, relid: Index) -> PgRelation {
+        let foreigntableid = (**(*root).simple_rte_array.add(relid as usize)).relid;
+
+        PgRelation::with_lock(foreigntableid, pg_sys::NoLock as pg_sys::LOCKMODE)
+    }
+
+    /// `JoinSide` of `baserel` as seen during planning
+    unsafe fn planned_join_side(
+        baserel: *mut RelOptInfo,
+        rel: &PgRelation,
+        desc: &PgTupleDesc,
+        target_vars: *mut List,
+    ) -> JoinSide {
+        let relid = (*baserel).relid;
+        let options = Self::options(rel);
+        let clauses = qual::classify_clauses(relid, desc, (*baserel).baserestrictinfo, |qual| {
+            T::supports_qual(&options, qual)
+        });
+        let attnums = Self::var_attnums(target_vars, relid)
+            .into_iter()
+            .map(|attnum| attnum as usize)
+            .collect::<Vec<_>>();
+
+        JoinSide {
+            columns: Self::column_names(desc, &attnums),
+            options,
+            quals: clauses.quals,
+        }
+    }
+
+    /// Sorted attribute numbers of the `Var`s of `relid` in `vars`
+    unsafe fn var_attnums(vars: *mut List, relid: Index) -> Vec<AttrNumber> {
+        let mut attnums = PgList::<pg_sys::Node>::from_pg(vars)
+            .iter_ptr()
+            .filter(|node| is_a(*node, pg_sys::NodeTag_T_Var))
+            .map(|node| node as *mut Var)
+            .filter(|var| (**var).varno == relid && (**var).varattno > 0)
+            .map(|var| (*var).varattno)
+            .collect::<Vec<_>>();
+
+        attnums.sort_unstable();
+        attnums.dedup();
+        attnums
+    }
+
+    unsafe extern "C" fn get_foreign_upper_paths(
+        root: *mut PlannerInfo,
+        stage: UpperRelationKind,
+        input_rel: *mut RelOptInfo,
+        output_rel: *mut RelOptInfo,
+        extra: *mut ::std::os::raw::c_void,
+    ) {
+        pg_sys::guard::guard(move || {
+            if stage == pg_sys::UpperRelationKind_UPPERREL_GROUP_AGG {
+                Self::add_aggregate_paths(
+                    root,
+                    input_rel,
+                    output_rel,
+                    extra as *mut GroupPathExtraData,
+                );
+            } else if stage == pg_sys::UpperRelationKind_UPPERREL_FINAL {
+                Self::add_final_paths(
+                    root,
+                    input_rel,
+                    output_rel,
+                    extra as *mut FinalPathExtraData,
+                );
+            }
+        })
+    }
+
+    /// Adds a path computing the query's `GROUP BY` and aggregates in `ForeignData::aggregate`
+    unsafe fn add_aggregate_paths(
+        root: *mut PlannerInfo,
+        input_rel: *mut RelOptInfo,
+        grouped_rel: *mut RelOptInfo,
+        extra: *mut GroupPathExtraData,
+    ) {
+        let parse = (*root).parse;
+
+        if (*input_rel).reloptkind != pg_sys::RelOptKind_RELOPT_BASEREL
+            || (*input_rel).fdw_private.is_null()
+            || (*extra).patype != pg_sys::PartitionwiseAggregateType_PARTITIONWISE_AGGREGATE_NONE
+            || !(*parse).groupingSets.is_null()
+            || !(*parse).havingQual.is_null()
+        {
+            return;
+        }
+
+        let plan_state =
+            PgBox::<FdwPlanState>::from_pg((*input_rel).fdw_private as *mut FdwPlanState);
+
+        if plan_state.has_local_conds || plan_state.has_pseudoconstant {
+            return;
+        }
+
+        // Only plain columns can be grouped by
+        let mut group_exprs = PgList::<pg_sys::Node>::new();
+
+        for clause in PgList::<SortGroupClause>::from_pg((*parse).groupClause).iter_ptr() {
+            let expr = pg_sys::get_sortgroupclause_expr(clause, (*parse).targetList);
+
+            if !is_a(expr, pg_sys::NodeTag_T_Var) {
+                return;
+            }
+
+            group_exprs.push(expr);
+        }
+
+        let group_exprs = group_exprs.into_pg();
+
+        // The grouped rel's target is computed on top of the grouped columns and aggregates,
+        // any other column reference has to be computed locally
+        let grouping_target =
+            (*root).upper_targets[pg_sys::UpperRelationKind_UPPERREL_GROUP_AGG as usize];
+        let target_exprs = pg_sys::pull_var_clause(
+            (*grouping_target).exprs as *mut Node,
+            (pg_sys::PVC_INCLUDE_AGGREGATES | pg_sys::PVC_RECURSE_PLACEHOLDERS) as i32,
+        );
+        let grouped_only = PgList::<pg_sys::Node>::from_pg(target_exprs)
+            .iter_ptr()
+            .all(|expr| {
+                !is_a(expr, pg_sys::NodeTag_T_Var)
+                    || pg_sys::list_member(group_exprs, expr as *const std::os::raw::c_void)
+            });
+
+        if !grouped_only {
+            return;
+        }
+
+        let scan_tlist = pg_sys::add_to_flat_tlist(
+            pg_sys::add_to_flat_tlist(std::ptr::null_mut(), group_exprs),
+            target_exprs,
+        );
+
+        let relid = (*input_rel).relid;
+        let foreigntableid = (**(*root).simple_rte_array.add(relid as usize)).relid;
+        let rel = PgRelation::with_lock(foreigntableid, pg_sys::NoLock as pg_sys::LOCKMODE);
+        let opts = Self::options(&rel);
+        let tupdesc = rel.tuple_desc();
+
+        match Aggregation::from_scan_tlist(scan_tlist, &tupdesc) {
+            Some(aggregation) if T::supports_aggregation(&opts, &aggregation) => {}
+            _ => return,
+        }
+
+        let rows = if group_exprs.is_null() {
+            1.0
+        } else {
+            pg_sys::estimate_num_groups(root, group_exprs, plan_state.rows, std::ptr::null_mut())
+        };
+        // Every row still has to be read remotely, but only the groups are returned
+        let startup_cost = plan_state.total_cost;
+        let total_cost = startup_cost + rows * pg_sys::cpu_tuple_cost;
+
+        let mut fdw_private = PgList::<std::os::raw::c_void>::new();
+        fdw_private.push(pg_sys::makeInteger(relid as _) as pgx::memcxt::void_mut_ptr);
+        fdw_private.push(std::ptr::null_mut());
+        fdw_private.push(scan_tlist as pgx::memcxt::void_mut_ptr);
+
+        pg_sys::add_path(
+            grouped_rel,
+            pg_sys::create_foreign_upper_path(
+                root,
+                grouped_rel,
+                grouping_target,
+                rows,
+                startup_cost,
+                total_cost,
                 std::ptr::null_mut(),
                 std::ptr::null_mut(),
+                fdw_private.into_pg(),
+            ) as *mut pg_sys::Path,
+        );
+    }
+
+    /// Adds a path applying the query's `LIMIT`/`OFFSET` in `ForeignData::execute`
+    unsafe fn add_final_paths(
+        root: *mut PlannerInfo,
+        _input_rel: *mut RelOptInfo,
+        final_rel: *mut RelOptInfo,
+        extra: *mut FinalPathExtraData,
+    ) {
+        let parse = (*root).parse;
+
+        if !(*extra).limit_needed || !Self::is_single_table_scan(parse) {
+            return;
+        }
+
+        let relid = match PgList::<pg_sys::Node>::from_pg((*(*parse).jointree).fromlist).head() {
+            Some(node) if is_a(node, pg_sys::NodeTag_T_RangeTblRef) => {
+                (*(node as *mut RangeTblRef)).rtindex as Index
+            }
+            _ => return,
+        };
+        let baserel = *(*root).simple_rel_array.add(relid as usize);
+
+        if (*baserel).reloptkind != pg_sys::RelOptKind_RELOPT_BASEREL
+            || (*baserel).fdw_private.is_null()
+        {
+            return;
+        }
+
+        let plan_state =
+            PgBox::<FdwPlanState>::from_pg((*baserel).fdw_private as *mut FdwPlanState);
+        let limit = match Limit::from_query(parse) {
+            Some(limit) if !plan_state.has_local_conds && !plan_state.has_pseudoconstant => limit,
+            _ => return,
+        };
+
+        let foreigntableid = (**(*root).simple_rte_array.add(relid as usize)).relid;
+        let rel = PgRelation::with_lock(foreigntableid, pg_sys::NoLock as pg_sys::LOCKMODE);
+        let opts = Self::options(&rel);
+        let tupdesc = rel.tuple_desc();
+
+        if !T::supports_limit(&opts) {
+            return;
+        }
+
+        let final_target = (*root).upper_targets[pg_sys::UpperRelationKind_UPPERREL_FINAL as usize];
+
+        // Whole-row and system column references can't be satisfied without a scanned relation
+        let plain_columns = PgList::<pg_sys::Node>::from_pg(pg_sys::pull_var_clause(
+            (*final_target).exprs as *mut Node,
+            pg_sys::PVC_RECURSE_PLACEHOLDERS as i32,
+        ))
+        .iter_ptr()
+        .all(|node| is_a(node, pg_sys::NodeTag_T_Var) && (*(node as *mut Var)).varattno > 0);
+
+        if !plain_columns {
+            return;
+        }
+
+        let pathkeys = (*root).sort_pathkeys;
+        let sorted = match sort::from_pathkeys(pathkeys, relid, &tupdesc) {
+            Some(keys) => keys.iter().all(|(_, key)| T::supports_sort(&opts, key)),
+            None => false,
+        };
+
+        if !sorted {
+            return;
+        }
+
+        let cost_multiplier = if pathkeys.is_null() {
+            1.0
+        } else {
+            SORT_COST_MULTIPLIER
+        };
+        let base_rows = plan_state.rows.max(1.0);
+        let rows = limit
+            .count
+            .map_or(base_rows, |count| base_rows.min(count as f64));
+        let fraction = ((limit.offset as f64 + rows) / base_rows).min(1.0);
+        let startup_cost = plan_state.startup_cost * cost_multiplier;
+        let total_cost = startup_cost
+            + (plan_state.total_cost - plan_state.startup_cost) * fraction * cost_multiplier;
+
+        let mut fdw_private = PgList::<std::os::raw::c_void>::new();
+        fdw_private.push(pg_sys::makeInteger(relid as _) as pgx::memcxt::void_mut_ptr);
+        fdw_private.push(limit.to_list() as pgx::memcxt::void_mut_ptr);
+
+        pg_sys::add_path(
+            final_rel,
+            pg_sys::create_foreign_upper_path(
+                root,
+                final_rel,
+                final_target,
+                pg_sys::clamp_row_est(rows),
+                startup_cost,
+                total_cost,
+                pathkeys,
                 std::ptr::null_mut(),
+                fdw_private.into_pg(),
             ) as *mut pg_sys::Path,
-        )
+        );
+    }
+
+    /// Whether the query only scans a single relation, without any processing that would
+    /// have to happen before a `LIMIT` is applied
+    unsafe fn is_single_table_scan(parse: *mut Query) -> bool {
+        let simple = !(*parse).hasAggs
+            && !(*parse).hasWindowFuncs
+            && !(*parse).hasTargetSRFs
+            && (*parse).groupClause.is_null()
+            && (*parse).groupingSets.is_null()
+            && (*parse).havingQual.is_null()
+            && (*parse).distinctClause.is_null()
+            && (*parse).rowMarks.is_null()
+            && (*parse).setOperations.is_null()
+            && PgList::<pg_sys::Node>::from_pg((*(*parse).jointree).fromlist).len() == 1;
+
+        #[cfg(feature = "pg13")]
+        let simple = simple && (*parse).limitOption != pg_sys::LimitOption_LIMIT_OPTION_WITH_TIES;
+
+        simple
     }
 
     unsafe extern "C" fn get_foreign_plan(
-        _root: *mut PlannerInfo,
-        baserel: *mut RelOptInfo,
-        _foreigntableid: Oid,
-        _best_path: *mut ForeignPath,
+        root: *mut PlannerInfo,
+        foreignrel: *mut RelOptInfo,
+        foreigntableid: Oid,
+        best_path: *mut ForeignPath,
         tlist: *mut List,
         scan_clauses: *mut List,
         outer_plan: *mut Plan,
     ) -> *mut ForeignScan {
-        let scan_relid = (*baserel).relid;
-        let scan_clauses = pg_sys::extract_actual_clauses(scan_clauses, false);
+        pg_sys::guard::guard(move || {
+            if (*foreignrel).reloptkind == pg_sys::RelOptKind_RELOPT_UPPER_REL {
+                return Self::get_foreign_upper_plan(root, best_path, tlist, outer_plan);
+            } else if (*foreignrel).reloptkind == pg_sys::RelOptKind_RELOPT_JOINREL {
+                return Self::get_foreign_join_plan(root, best_path, tlist, outer_plan);
+            }
+
+            let scan_relid = (*foreignrel).relid;
+            let rel = PgRelation::with_lock(foreigntableid, pg_sys::NoLock as pg_sys::LOCKMODE);
+            let opts = Self::options(&rel);
+            let tupdesc = rel.tuple_desc();
+
+            let clauses = qual::classify_clauses(scan_relid, &tupdesc, scan_clauses, |qual| {
+                T::supports_qual(&opts, qual)
+            });
+
+            let attnums = Self::referenced_attnums(foreignrel, scan_clauses, tupdesc.len());
+            let sort_keys = sort::from_pathkeys((*best_path).path.pathkeys, scan_relid, &tupdesc)
+                .unwrap_or_default();
+
+            pg_sys::make_foreignscan(
+                tlist,
+                pg_sys::extract_actual_clauses(clauses.local_conds, false),
+                scan_relid,
+                clauses.params,
+                Self::scan_fdw_private(
+                    scan_relid,
+                    foreigntableid,
+                    clauses.remote_conds,
+                    attnums,
+                    sort::to_list(&sort_keys),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                ),
+                std::ptr::null_mut(),
+                clauses.remote_conds,
+                outer_plan,
+            )
+        })
+    }
+
+    /// Plan for a path created by `get_foreign_upper_paths`. These scan a single foreign
+    /// table, but without a `scanrelid` the scanned columns are described by `fdw_scan_tlist`.
+    unsafe fn get_foreign_upper_plan(
+        root: *mut PlannerInfo,
+        best_path: *mut ForeignPath,
+        tlist: *mut List,
+        outer_plan: *mut Plan,
+    ) -> *mut ForeignScan {
+        let path_private = PgList::<pg_sys::Node>::from_pg((*best_path).fdw_private);
+        let relid = path_private
+            .get_ptr(FDW_PATH_PRIVATE_RELID)
+            .map_or(0, |relid| {
+                (*(relid as *mut pg_sys::Value)).val.ival as Index
+            });
+        let limit = path_private
+            .get_ptr(FDW_PATH_PRIVATE_LIMIT)
+            .map_or(std::ptr::null_mut(), |limit| limit as *mut List);
+        let aggregation = path_private
+            .get_ptr(FDW_PATH_PRIVATE_AGGREGATION)
+            .map_or(std::ptr::null_mut(), |aggregation| aggregation as *mut List);
+
+        let baserel = *(*root).simple_rel_array.add(relid as usize);
+        let foreigntableid = (**(*root).simple_rte_array.add(relid as usize)).relid;
+        let rel = PgRelation::with_lock(foreigntableid, pg_sys::NoLock as pg_sys::LOCKMODE);
+        let opts = Self::options(&rel);
+        let tupdesc = rel.tuple_desc();
+
+        let clauses =
+            qual::classify_clauses(relid, &tupdesc, (*baserel).baserestrictinfo, |qual| {
+                T::supports_qual(&opts, qual)
+            });
+
+        // Aggregated scans return the columns of the aggregation, the others only reference
+        // plain columns
+        let (scan_tlist, attnums) = if aggregation.is_null() {
+            let mut vars = PgList::<Var>::from_pg(pg_sys::pull_var_clause(
+                tlist as *mut Node,
+                pg_sys::PVC_RECURSE_PLACEHOLDERS as i32,
+            ))
+            .iter_ptr()
+            .collect::<Vec<_>>();
+
+            // In table order, like the `columns` passed to `execute`
+            vars.sort_by_key(|var| (**var).varattno);
+
+            let mut sorted = PgList::<Var>::new();
+
+            vars.into_iter().for_each(|var| sorted.push(var));
+
+            let scan_tlist = pg_sys::add_to_flat_tlist(std::ptr::null_mut(), sorted.into_pg());
+            let attnums = PgList::<TargetEntry>::from_pg(scan_tlist).iter_ptr().fold(
+                std::ptr::null_mut(),
+                |attnums, tle| {
+                    pg_sys::lappend_int(attnums, (*((*tle).expr as *mut Var)).varattno as i32)
+                },
+            );
+
+            (scan_tlist, attnums)
+        } else {
+            (aggregation, std::ptr::null_mut())
+        };
+        let sort_keys =
+            sort::from_pathkeys((*best_path).path.pathkeys, relid, &tupdesc).unwrap_or_default();
 
         pg_sys::make_foreignscan(
             tlist,
-            scan_clauses,
-            scan_relid,
-            scan_clauses,
             std::ptr::null_mut(),
+            0,
+            clauses.params,
+            Self::scan_fdw_private(
+                relid,
+                foreigntableid,
+                clauses.remote_conds,
+                attnums,
+                sort::to_list(&sort_keys),
+                limit,
+                aggregation,
+            ),
+            scan_tlist,
             std::ptr::null_mut(),
+            outer_plan,
+        )
+    }
+
+    /// Plan for a path created by `get_foreign_join_paths`. The outer table is described like
+    /// a base relation scan, with the inner table in `FDW_PRIVATE_JOIN`.
+    unsafe fn get_foreign_join_plan(
+        root: *mut PlannerInfo,
+        best_path: *mut ForeignPath,
+        tlist: *mut List,
+        outer_plan: *mut Plan,
+    ) -> *mut ForeignScan {
+        let path_private = (*best_path).fdw_private;
+        let outer_relid =
+            Self::fdw_private_int(path_private, FDW_JOIN_PATH_PRIVATE_OUTER_RELID) as Index;
+        let inner_relid =
+            Self::fdw_private_int(path_private, FDW_JOIN_PATH_PRIVATE_INNER_RELID) as Index;
+        let tlist_vars =
+            pg_sys::pull_var_clause(tlist as *mut Node, pg_sys::PVC_RECURSE_PLACEHOLDERS as i32);
+
+        // The scan tuple has the referenced columns of the outer table followed by the ones
+        // of the inner table
+        let mut scan_tlist = std::ptr::null_mut();
+        let mut sides = vec![];
+
+        for &relid in &[outer_relid, inner_relid] {
+            let baserel = *(*root).simple_rel_array.add(relid as usize);
+            let rel = Self::planned_relation(root, relid);
+            let opts = Self::options(&rel);
+            let tupdesc = rel.tuple_desc();
+            let clauses =
+                qual::classify_clauses(relid, &tupdesc, (*baserel).baserestrictinfo, |qual| {
+                    T::supports_qual(&opts, qual)
+                });
+            let mut attnums = std::ptr::null_mut();
+
+            for attnum in Self::var_attnums(tlist_vars, relid) {
+                let attr = tupdesc.get(attnum as usize - 1).unwrap();
+                let var = pg_sys::makeVar(
+                    relid,
+                    attnum,
+                    attr.atttypid,
+                    attr.atttypmod,
+                    attr.attcollation,
+                    0,
+                );
+
+                scan_tlist = pg_sys::add_to_flat_tlist(
+                    scan_tlist,
+                    pg_sys::lappend(std::ptr::null_mut(), var as pgx::memcxt::void_mut_ptr),
+                );
+                attnums = pg_sys::lappend_int(attnums, attnum as i32);
+            }
+
+            sides.push((rel.rd_id, clauses, attnums));
+        }
+
+        let (inner_oid, inner_clauses, inner_attnums) = sides.pop().unwrap();
+        let (outer_oid, outer_clauses, outer_attnums) = sides.pop().unwrap();
+
+        let mut join_private = PgList::<std::os::raw::c_void>::new();
+        join_private.push(pg_sys::makeInteger(inner_relid as _) as pgx::memcxt::void_mut_ptr);
+        join_private.push(pg_sys::makeInteger(inner_oid as _) as pgx::memcxt::void_mut_ptr);
+        join_private.push(inner_clauses.remote_conds as pgx::memcxt::void_mut_ptr);
+        join_private.push(inner_attnums as pgx::memcxt::void_mut_ptr);
+        join_private.push(pg_sys::makeInteger(Self::fdw_private_int(
+            path_private,
+            FDW_JOIN_PATH_PRIVATE_JOINTYPE,
+        ) as _) as pgx::memcxt::void_mut_ptr);
+        join_private.push(
+            Self::fdw_private_list(path_private, FDW_JOIN_PATH_PRIVATE_CLAUSES)
+                as pgx::memcxt::void_mut_ptr,
+        );
+
+        let fdw_private = pg_sys::lappend(
+            Self::scan_fdw_private(
+                outer_relid,
+                outer_oid,
+                outer_clauses.remote_conds,
+                outer_attnums,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            ),
+            join_private.into_pg() as pgx::memcxt::void_mut_ptr,
+        );
+
+        // Runtime values of the outer table's quals come first
+        pg_sys::make_foreignscan(
+            tlist,
+            std::ptr::null_mut(),
+            0,
+            pg_sys::list_concat(outer_clauses.params, inner_clauses.params),
+            fdw_private,
+            scan_tlist,
             std::ptr::null_mut(),
             outer_plan,
         )
     }
 
-    extern "C" fn begin_foreign_scan(node: *mut ForeignScanState, _eflags: ::std::os::raw::c_int) {
-        let mut fdw_state = PgBox::<Self>::alloc0();
-        let mut n = PgBox::<ForeignScanState>::from_pg(node);
-        let rel = unsafe { PgRelation::from_pg(n.ss.ss_currentRelation) };
-        let opts = FdwOptions::from_relation(&rel);
+    unsafe fn scan_fdw_private(
+        relid: Index,
+        foreigntableid: Oid,
+        remote_conds: *mut List,
+        attnums: *mut List,
+        sort: *mut List,
+        limit: *mut List,
+        aggregation: *mut List,
+    ) -> *mut List {
+        let mut fdw_private = PgList::<std::os::raw::c_void>::new();
+
+        fdw_private.push(pg_sys::makeInteger(relid as _) as pgx::memcxt::void_mut_ptr);
+        fdw_private.push(pg_sys::makeInteger(foreigntableid as _) as pgx::memcxt::void_mut_ptr);
+        fdw_private.push(remote_conds as pgx::memcxt::void_mut_ptr);
+        fdw_private.push(attnums as pgx::memcxt::void_mut_ptr);
+        fdw_private.push(sort as pgx::memcxt::void_mut_ptr);
+        fdw_private.push(limit as pgx::memcxt::void_mut_ptr);
+        fdw_private.push(aggregation as pgx::memcxt::void_mut_ptr);
+        fdw_private.into_pg()
+    }
+
+    unsafe fn fdw_private_int(fdw_private: *mut List, index: usize) -> i32 {
+        PgList::<pg_sys::Value>::from_pg(fdw_private)
+            .get_ptr(index)
+            .map_or(0, |value| (*value).val.ival)
+    }
+
+    unsafe fn fdw_private_list(fdw_private: *mut List, index: usize) -> *mut List {
+        PgList::<List>::from_pg(fdw_private)
+            .get_ptr(index)
+            .unwrap_or(std::ptr::null_mut())
+    }
+
+    /// Integer list of the attributes referenced by the target list or `scan_clauses`
+    unsafe fn referenced_attnums(
+        baserel: *mut RelOptInfo,
+        scan_clauses: *mut List,
+        natts: usize,
+    ) -> *mut List {
+        let relid = (*baserel).relid;
+        let mut attrs: *mut Bitmapset = std::ptr::null_mut();
+
+        pg_sys::pull_varattnos(
+            (*(*baserel).reltarget).exprs as *mut Node,
+            relid,
+            &mut attrs,
+        );
+        PgList::<RestrictInfo>::from_pg(scan_clauses)
+            .iter_ptr()
+            .for_each(|rinfo| {
+                pg_sys::pull_varattnos((*rinfo).clause as *mut Node, relid, &mut attrs)
+            });
+
+        let mut attnums: *mut List = std::ptr::null_mut();
+        let mut member = pg_sys::bms_next_member(attrs, -1);
+
+        while member >= 0 {
+            let attnum = member + pg_sys::FirstLowInvalidHeapAttributeNumber;
+
+            // A whole-row reference needs every column
+            if attnum == 0 {
+                return (1..=natts).fold(std::ptr::null_mut(), |list, attnum| {
+                    pg_sys::lappend_int(list, attnum as i32)
+                });
+            }
+
+            if attnum > 0 {
+                attnums = pg_sys::lappend_int(attnums, attnum);
+            }
+
+            member = pg_sys::bms_next_member(attrs, member);
+        }
 
-        fdw_state.state = T::begin(&opts);
-        fdw_state.itr = std::ptr::null_mut();
+        attnums
+    }
 
-        n.fdw_state = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
-        // (*node).fdw_state = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
+    extern "C" fn begin_foreign_scan(node: *mut ForeignScanState, _eflags: ::std::os::raw::c_int) {
+        pg_sys::guard::guard(move || {
+            let mut n = PgBox::<ForeignScanState>::from_pg(node);
+            let plan = PgBox::<ForeignScan>::from_pg(n.ss.ps.plan as *mut ForeignScan);
+
+            // Scans of upper relations don't have a `ss_currentRelation`
+            let relation = if n.ss.ss_currentRelation.is_null() {
+                unsafe {
+                    pg_sys::relation_open(
+                        Self::fdw_private_int(plan.fdw_private, FDW_PRIVATE_TABLE_OID) as Oid,
+                        pg_sys::NoLock as pg_sys::LOCKMODE,
+                    )
+                }
+            } else {
+                n.ss.ss_currentRelation
+            };
+
+            let rel = unsafe { PgRelation::from_pg(relation) };
+            // Upper and join relations use the user of the scanned table
+            let rti = match plan.scan.scanrelid {
+                0 => unsafe { Self::fdw_private_int(plan.fdw_private, FDW_PRIVATE_RELID) as Index },
+                scanrelid => scanrelid,
+            };
+            let userid = unsafe { Self::check_as_user(n.ss.ps.state, rti) };
+            let opts = Self::with_defaults(FdwOptions::for_user(&rel, userid));
+
+            let fdw_state = FdwState {
+                state: T::begin(&opts).unwrap_or_else(|err| err.report()),
+                itr: std::ptr::null_mut(),
+                relation,
+                fdw_private: plan.fdw_private,
+                param_exprs: unsafe {
+                    pg_sys::ExecInitExprList(plan.fdw_exprs, node as *mut PlanState)
+                },
+                rescanned: false,
+                column_opts: opts.column_opts.clone(),
+                // Partitions are claimed one by one so each is scanned once across the processes
+                partitions: if plan.scan.plan.parallel_aware {
+                    T::parallel_partitions(&opts).unwrap_or(1).max(1)
+                } else {
+                    0
+                },
+                next_partition: 0,
+                parallel: std::ptr::null_mut(),
+                modified: false,
+                batch_size: 1,
+                batch: vec![],
+            };
+
+            // Dropped when the executor's memory is released, on errors as well
+            n.fdw_state = PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(fdw_state)
+                as pgx::memcxt::void_mut_ptr;
+        })
     }
 
     unsafe extern "C" fn iterate_foreign_scan(node: *mut ForeignScanState) -> *mut TupleTableSlot {
-        let mut n = PgBox::<ForeignScanState>::from_pg(node);
-        let mut fdw_state = PgBox::<Self>::from_pg(n.fdw_state as *mut Self);
-        let mut fdw_itr = PgBox::<T::RowIterator>::from_pg(fdw_state.itr);
-
-        let rel = PgRelation::from_pg(n.ss.ss_currentRelation);
-
-        let tupdesc = PgTupleDesc::from_pg_copy(rel.rd_att);
-
-        let slot = Self::exec_clear_tuple(n.ss.ss_ScanTupleSlot);
-        let (item, itr_ptr) = Self::itr_next(&mut fdw_itr, &mut fdw_state, &tupdesc);
+        pg_sys::guard::guard(move || {
+            let mut n = PgBox::<ForeignScanState>::from_pg(node);
+            let mut fdw_state = PgBox::<Self>::from_pg(n.fdw_state as *mut Self);
+            let mut fdw_itr = PgBox::<T::RowIterator>::from_pg(fdw_state.itr);
+
+            let tupdesc = PgTupleDesc::from_pg_copy((*fdw_state.relation).rd_att);
+
+            let attnums = PgList::<std::os::raw::c_void>::from_pg(Self::fdw_private_list(
+                fdw_state.fdw_private,
+                FDW_PRIVATE_ATTNUMS,
+            ))
+            .iter_int()
+            .map(|attnum| attnum as usize)
+            .collect::<Vec<_>>();
+
+            // Position of each returned column in the scan tuple, which only has the columns of
+            // `fdw_scan_tlist` when scanning an upper or join relation. Aggregated and joined rows
+            // always have a value for each of them.
+            let scan_natts = (*(*n.ss.ss_ScanTupleSlot).tts_tupleDescriptor).natts as usize;
+            let (natts, positions) = if (*(n.ss.ps.plan as *mut ForeignScan)).scan.scanrelid != 0 {
+                (
+                    Some(tupdesc.len()),
+                    attnums.iter().map(|attnum| attnum - 1).collect::<Vec<_>>(),
+                )
+            } else if Self::fdw_private_list(fdw_state.fdw_private, FDW_PRIVATE_AGGREGATION)
+                .is_null()
+                && Self::fdw_private_list(fdw_state.fdw_private, FDW_PRIVATE_JOIN).is_null()
+            {
+                (Some(tupdesc.len()), (0..scan_natts).collect())
+            } else {
+                (None, (0..scan_natts).collect())
+            };
+
+            let slot = Self::exec_clear_tuple(n.ss.ss_ScanTupleSlot);
+            let (item, itr_ptr) = if fdw_state.partitions > 0 {
+                Self::partition_next(
+                    &mut fdw_itr,
+                    &mut fdw_state,
+                    &tupdesc,
+                    &attnums,
+                    n.ss.ps.ps_ExprContext,
+                )
+            } else {
+                Self::itr_next(
+                    &mut fdw_itr,
+                    &mut fdw_state,
+                    &tupdesc,
+                    n.ss.ps.ps_ExprContext,
+                )
+            };
 
-        fdw_state.itr = itr_ptr;
-        n.fdw_state = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
+            fdw_state.itr = itr_ptr;
+            n.fdw_state = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
 
-        item.map_or(slot, |row| Self::store_tuple(slot, &tupdesc, row))
+            item.map_or(slot, |row| {
+                Self::store_tuple(slot, natts, &attnums, &positions, row)
+            })
+        })
     }
 
     fn itr_next(
         fdw_itr: &mut PgBox<<T as ForeignData>::RowIterator>,
         fdw_state: &mut PgBox<FdwState<T>>,
         tupdesc: &PgTupleDesc,
+        econtext: *mut ExprContext,
     ) -> (
         Option<Vec<<T as ForeignData>::Item>>,
         *mut <T as ForeignData>::RowIterator,
     ) {
         if fdw_itr.is_null() {
-            let mut itr = fdw_state.state.execute(&tupdesc);
+            let mut values = unsafe { Self::param_values(fdw_state, econtext) }.into_iter();
+            let FdwState {
+                state,
+                column_opts,
+                rescanned,
+                relation,
+                fdw_private,
+                ..
+            } = &mut **fdw_state;
+            let (aggregation, join) = unsafe {
+                (
+                    Self::fdw_private_list(*fdw_private, FDW_PRIVATE_AGGREGATION),
+                    Self::fdw_private_list(*fdw_private, FDW_PRIVATE_JOIN),
+                )
+            };
+            let scan =
+                unsafe { Self::planned_scan(*fdw_private, tupdesc, column_opts, &mut values) };
+
+            let itr = if !join.is_null() {
+                let join = unsafe {
+                    Self::scan_join(
+                        join,
+                        *relation,
+                        *fdw_private,
+                        (*econtext).ecxt_estate,
+                        scan,
+                        &mut values,
+                    )
+                };
+
+                state.join(&join)
+            } else if !aggregation.is_null() {
+                match unsafe { Aggregation::from_scan_tlist(aggregation, tupdesc) } {
+                    Some(aggregation) => state.aggregate(tupdesc, &scan.quals, &aggregation),
+                    None => error!("Unsupported aggregation in foreign scan"),
+                }
+            } else {
+                let restarted = if *rescanned {
+                    state.rescan(&scan)
+                } else {
+                    Ok(None)
+                };
+
+                restarted.and_then(|itr| match itr {
+                    Some(itr) => Ok(itr),
+                    None => state.execute(&scan),
+                })
+            };
+            let mut itr = itr.unwrap_or_else(|err| err.report());
             let item = itr.next();
             let itr_ptr = Box::into_raw(Box::new(itr)) as *mut T::RowIterator;
 
@@ -196,33 +1831,235 @@ impl<T: ForeignData> FdwState<T> {
         }
     }
 
+    /// The scan planned in `fdw_private`, with the `QualValue::Param`s taken from `values`.
+    /// Upper and join relations scan the outer table this way.
+    unsafe fn planned_scan<'a>(
+        fdw_private: *mut List,
+        tupdesc: &'a PgTupleDesc<'a>,
+        column_opts: &'a ColumnOptions,
+        values: &mut impl Iterator<Item = Option<pg_sys::Datum>>,
+    ) -> Scan<'a> {
+        let attnums = PgList::<std::os::raw::c_void>::from_pg(Self::fdw_private_list(
+            fdw_private,
+            FDW_PRIVATE_ATTNUMS,
+        ))
+        .iter_int()
+        .map(|attnum| attnum as usize)
+        .collect::<Vec<_>>();
+
+        Scan {
+            desc: tupdesc,
+            column_opts,
+            quals: qual::from_remote_conds(
+                Self::fdw_private_int(fdw_private, FDW_PRIVATE_RELID) as Index,
+                tupdesc,
+                Self::fdw_private_list(fdw_private, FDW_PRIVATE_REMOTE_CONDS),
+                values,
+            ),
+            columns: Self::column_names(tupdesc, &attnums),
+            sort: sort::from_list(
+                Self::fdw_private_list(fdw_private, FDW_PRIVATE_SORT),
+                tupdesc,
+            ),
+            limit: Limit::from_list(Self::fdw_private_list(fdw_private, FDW_PRIVATE_LIMIT)),
+        }
+    }
+
+    /// Next row of a parallel scan, moving on to the next unclaimed partition when the current
+    /// one is exhausted
+    fn partition_next(
+        fdw_itr: &mut PgBox<<T as ForeignData>::RowIterator>,
+        fdw_state: &mut PgBox<FdwState<T>>,
+        tupdesc: &PgTupleDesc,
+        attnums: &[usize],
+        econtext: *mut ExprContext,
+    ) -> (
+        Option<Vec<<T as ForeignData>::Item>>,
+        *mut <T as ForeignData>::RowIterator,
+    ) {
+        if !fdw_itr.is_null() {
+            if let Some(row) = fdw_itr.next() {
+                return (Some(row), fdw_itr.as_ptr());
+            }
+
+            fdw_state.drop_iterator();
+        }
+
+        // Parallel paths aren't parameterized by a join, but quals may still reference query
+        // parameters and stable function calls
+        let fdw_private = fdw_state.fdw_private;
+        let quals = unsafe {
+            let mut values = Self::param_values(fdw_state, econtext).into_iter();
+
+            qual::from_remote_conds(
+                Self::fdw_private_int(fdw_private, FDW_PRIVATE_RELID) as Index,
+                tupdesc,
+                Self::fdw_private_list(fdw_private, FDW_PRIVATE_REMOTE_CONDS),
+                &mut values,
+            )
+        };
+        let columns = Self::column_names(tupdesc, attnums);
+
+        while let Some(partition) = fdw_state.next_partition() {
+            let FdwState {
+                state, column_opts, ..
+            } = &mut **fdw_state;
+            let scan = Scan {
+                desc: tupdesc,
+                column_opts,
+                quals: quals.clone(),
+                columns: columns.clone(),
+                sort: vec![],
+                limit: None,
+            };
+            let mut itr = state
+                .execute_partition(&scan, partition)
+                .unwrap_or_else(|err| err.report());
+
+            if let Some(row) = itr.next() {
+                return (Some(row), Box::into_raw(Box::new(itr)));
+            }
+        }
+
+        (None, std::ptr::null_mut())
+    }
+
+    /// Values of the `Param` quals, computed at the start of the scan
+    unsafe fn param_values(
+        fdw_state: &PgBox<FdwState<T>>,
+        econtext: *mut ExprContext,
+    ) -> Vec<Option<pg_sys::Datum>> {
+        let param_exprs = fdw_state.param_exprs;
+
+        PgMemoryContexts::For((*econtext).ecxt_per_query_memory).switch_to(|_| {
+            PgList::<ExprState>::from_pg(param_exprs)
+                .iter_ptr()
+                .map(|state| {
+                    let mut isnull = false;
+                    let datum = (*state).evalfunc.unwrap()(state, econtext, &mut isnull);
+
+                    if isnull {
+                        None
+                    } else {
+                        Some(datum)
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+    }
+
+    fn column_names(tupdesc: &PgTupleDesc, attnums: &[usize]) -> Vec<String> {
+        attnums
+            .iter()
+            .filter_map(|attnum| tupdesc.get(attnum - 1))
+            .map(|attr| attr.name().into())
+            .collect()
+    }
+
+    /// Rebuilds the `Join` planned by `get_foreign_join_plan` from the `FDW_PRIVATE_JOIN` list
+    unsafe fn scan_join(
+        join_private: *mut List,
+        relation: Relation,
+        fdw_private: *mut List,
+        estate: *mut EState,
+        outer: Scan,
+        values: &mut impl Iterator<Item = Option<pg_sys::Datum>>,
+    ) -> Join {
+        let outer_rel = PgRelation::from_pg(relation);
+        let outer_relid = Self::fdw_private_int(fdw_private, FDW_PRIVATE_RELID) as Index;
+        let inner_relid = Self::fdw_private_int(join_private, FDW_JOIN_PRIVATE_RELID) as Index;
+        let inner_rel = PgRelation::with_lock(
+            Self::fdw_private_int(join_private, FDW_JOIN_PRIVATE_TABLE_OID) as Oid,
+            pg_sys::NoLock as pg_sys::LOCKMODE,
+        );
+        let inner_desc = inner_rel.tuple_desc();
+        let inner_attnums = PgList::<std::os::raw::c_void>::from_pg(Self::fdw_private_list(
+            join_private,
+            FDW_JOIN_PRIVATE_ATTNUMS,
+        ))
+        .iter_int()
+        .map(|attnum| attnum as usize)
+        .collect::<Vec<_>>();
+        let inner_quals = qual::from_remote_conds(
+            inner_relid,
+            &inner_desc,
+            Self::fdw_private_list(join_private, FDW_JOIN_PRIVATE_REMOTE_CONDS),
+            values,
+        );
+
+        let kind = JoinKind::from_pg(
+            Self::fdw_private_int(join_private, FDW_JOIN_PRIVATE_JOINTYPE) as JoinType,
+        );
+        let clauses = join::join_clauses(
+            Self::fdw_private_list(join_private, FDW_JOIN_PRIVATE_CLAUSES),
+            &join::JoinRel {
+                relid: outer_relid,
+                desc: outer.desc,
+            },
+            &join::JoinRel {
+                relid: inner_relid,
+                desc: &inner_desc,
+            },
+        );
+
+        match (kind, clauses) {
+            (Some(kind), Some(clauses)) => Join {
+                kind,
+                outer: JoinSide {
+                    // Each table is accessed as the user `begin` got the options of
+                    options: Self::with_defaults(FdwOptions::for_user(
+                        &outer_rel,
+                        Self::check_as_user(estate, outer_relid),
+                    )),
+                    quals: outer.quals,
+                    columns: outer.columns,
+                },
+                inner: JoinSide {
+                    options: Self::with_defaults(FdwOptions::for_user(
+                        &inner_rel,
+                        Self::check_as_user(estate, inner_relid),
+                    )),
+                    quals: inner_quals,
+                    columns: Self::column_names(&inner_desc, &inner_attnums),
+                },
+                clauses,
+            },
+            _ => error!("Unsupported join in foreign scan"),
+        }
+    }
+
+    /// Stores `row` into `slot`. A row either has a value for each of the relation's `natts`
+    /// columns, or one for each of `positions`.
     fn store_tuple(
         slot: *mut TupleTableSlot,
-        tupdesc: &PgTupleDesc,
+        natts: Option<usize>,
+        attnums: &[usize],
+        positions: &[usize],
         row: Vec<<T as ForeignData>::Item>,
     ) -> *mut TupleTableSlot {
-        let attrs_len = tupdesc.len();
+        let slot_desc = unsafe { (*slot).tts_tupleDescriptor };
+        let attrs_len = unsafe { (*slot_desc).natts as usize };
         let mut nulls = vec![true; attrs_len];
         let mut datums = vec![0 as pg_sys::Datum; attrs_len];
-        let mut row_iter = row.into_iter();
-
-        for (i, _attr) in tupdesc.iter().enumerate() {
-            if let Some(row_i) = row_iter.next() {
-                match row_i.into_datum() {
-                    Some(datum) => {
-                        datums[i] = datum;
-                        nulls[i] = false;
-                    }
-                    None => continue,
-                }
-            } else {
-                continue;
+        let full_row = natts == Some(row.len());
+        let mut row = row.into_iter().map(Some).collect::<Vec<_>>();
+
+        // Columns the query doesn't reference are left NULL
+        for (i, position) in positions.iter().enumerate() {
+            let index = if full_row { attnums[i] - 1 } else { i };
+
+            if let Some(datum) = row
+                .get_mut(index)
+                .and_then(Option::take)
+                .and_then(|item| item.into_datum())
+            {
+                datums[*position] = datum;
+                nulls[*position] = false;
             }
         }
 
         unsafe {
-            let tuple =
-                pg_sys::heap_form_tuple(tupdesc.as_ptr(), datums.as_mut_ptr(), nulls.as_mut_ptr());
+            let tuple = pg_sys::heap_form_tuple(slot_desc, datums.as_mut_ptr(), nulls.as_mut_ptr());
 
             pg_sys::ExecStoreHeapTuple(tuple, slot, false)
         }
@@ -244,85 +2081,244 @@ impl<T: ForeignData> FdwState<T> {
         slot
     }
 
-    unsafe extern "C" fn re_scan_foreign_scan(_node: *mut ForeignScanState) {}
+    unsafe extern "C" fn re_scan_foreign_scan(node: *mut ForeignScanState) {
+        pg_sys::guard::guard(move || {
+            let n = PgBox::<ForeignScanState>::from_pg(node);
+            let mut fdw_state = PgBox::<Self>::from_pg(n.fdw_state as *mut Self);
+
+            // Parameter values may have changed, the next iteration restarts the scan with the
+            // new ones
+            fdw_state.drop_iterator();
+            fdw_state.rescanned = true;
+            fdw_state.next_partition = 0;
+        })
+    }
+
+    unsafe extern "C" fn end_foreign_scan(node: *mut ForeignScanState) {
+        pg_sys::guard::guard(move || {
+            let n = PgBox::<ForeignScanState>::from_pg(node);
+
+            if n.fdw_state.is_null() {
+                return;
+            }
+
+            let mut fdw_state = PgBox::<Self>::from_pg(n.fdw_state as *mut Self);
+
+            fdw_state.drop_iterator();
+            fdw_state
+                .state
+                .end_scan()
+                .unwrap_or_else(|err| err.report());
 
-    unsafe extern "C" fn end_foreign_scan(_node: *mut ForeignScanState) {}
+            if n.ss.ss_currentRelation.is_null() {
+                pg_sys::relation_close(fdw_state.relation, pg_sys::NoLock as pg_sys::LOCKMODE);
+            }
+        })
+    }
 
     unsafe extern "C" fn add_foreign_update_targets(
         parsetree: *mut Query,
         _target_rte: *mut RangeTblEntry,
         target_relation: Relation,
     ) {
-        let rel = PgRelation::from_pg(target_relation);
-        let opts = FdwOptions::from_relation(&rel);
-        let tupdesc = PgTupleDesc::from_pg_copy((*target_relation).rd_att);
-
-        if let Some(keys) = T::indices(&opts) {
-            let mut list = PgList::<TargetEntry>::from_pg((*parsetree).targetList);
-            tupdesc
-                .iter()
-                .filter(|attr| keys.contains(&attr.name().into()))
-                .for_each(|attr| {
-                    let var = pg_sys::makeVar(
-                        (*parsetree).resultRelation as pg_sys::Index,
-                        attr.attnum,
-                        attr.atttypid,
-                        attr.atttypmod,
-                        attr.attcollation,
-                        0,
-                    );
-
-                    let ckey = std::ffi::CString::new(attr.name()).unwrap();
-                    let tle = pg_sys::makeTargetEntry(
-                        var as *mut pg_sys::Expr,
-                        (list.len() + 1) as i16,
-                        pg_sys::pstrdup(ckey.as_ptr()),
-                        true,
-                    );
-
-                    list.push(tle);
-                });
-
-            (*parsetree).targetList = list.into_pg();
-        }
+        pg_sys::guard::guard(move || {
+            let rel = PgRelation::from_pg(target_relation);
+            let opts = Self::options(&rel);
+            let tupdesc = PgTupleDesc::from_pg_copy((*target_relation).rd_att);
+
+            if let Some(keys) = T::indices(&opts) {
+                let mut list = PgList::<TargetEntry>::from_pg((*parsetree).targetList);
+                tupdesc
+                    .iter()
+                    .filter(|attr| keys.contains(&attr.name().into()))
+                    .for_each(|attr| {
+                        let var = pg_sys::makeVar(
+                            (*parsetree).resultRelation as pg_sys::Index,
+                            attr.attnum,
+                            attr.atttypid,
+                            attr.atttypmod,
+                            attr.attcollation,
+                            0,
+                        );
+
+                        let ckey = std::ffi::CString::new(attr.name()).unwrap();
+                        let tle = pg_sys::makeTargetEntry(
+                            var as *mut pg_sys::Expr,
+                            (list.len() + 1) as i16,
+                            pg_sys::pstrdup(ckey.as_ptr()),
+                            true,
+                        );
+
+                        list.push(tle);
+                    });
+
+                (*parsetree).targetList = list.into_pg();
+            }
+        })
     }
 
     extern "C" fn begin_foreign_modify(
-        _mtstate: *mut ModifyTableState,
+        mtstate: *mut ModifyTableState,
         rinfo: *mut ResultRelInfo,
         _fdw_private: *mut List,
         _subplan_index: ::std::os::raw::c_int,
         _eflags: ::std::os::raw::c_int,
     ) {
-        let mut fdw_state = PgBox::<Self>::alloc0();
-        let mut rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
-        let rel = unsafe { PgRelation::from_pg(rinfo_box.ri_RelationDesc) };
+        pg_sys::guard::guard(move || {
+            let inserting = unsafe { (*mtstate).operation } == pg_sys::CmdType_CMD_INSERT;
+
+            unsafe { Self::begin_modify((*mtstate).ps.state, rinfo, inserting) }
+        })
+    }
+
+    /// Inserts into the table by `COPY FROM`, or of rows routed into a foreign partition of a
+    /// partitioned table. An `UPDATE` can't move rows into a partition it updates later on.
+    unsafe extern "C" fn begin_foreign_insert(
+        mtstate: *mut ModifyTableState,
+        rinfo: *mut ResultRelInfo,
+    ) {
+        pg_sys::guard::guard(move || {
+            let plan = (*mtstate).ps.plan as *mut pg_sys::ModifyTable;
+
+            // The routed rows would be modified again by the update of the partition. Result
+            // relations are updated in order, the ones before the current subplan are done.
+            if !plan.is_null()
+                && (*plan).operation == pg_sys::CmdType_CMD_UPDATE
+                && (!(*rinfo).ri_FdwState.is_null() || (*rinfo).ri_usesFdwDirectModify)
+                && rinfo
+                    > (*mtstate)
+                        .resultRelInfo
+                        .add((*mtstate).mt_whichplan as usize)
+            {
+                let rel = PgRelation::from_pg((*rinfo).ri_RelationDesc);
+
+                FdwError::new(
+                    PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                    format!(
+                        "cannot route tuples into foreign table to be updated \"{}\"",
+                        rel.name()
+                    ),
+                )
+                .report();
+            }
 
-        let opts = FdwOptions::from_relation(&rel);
+            Self::begin_modify((*mtstate).ps.state, rinfo, true)
+        })
+    }
 
-        fdw_state.state = T::begin(&opts);
-        fdw_state.itr = std::ptr::null_mut();
+    unsafe fn begin_modify(estate: *mut EState, rinfo: *mut ResultRelInfo, inserting: bool) {
+        let mut rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
+        let rel = PgRelation::from_pg(rinfo_box.ri_RelationDesc);
+        let userid = Self::check_as_user(estate, rinfo_box.ri_RangeTableIndex);
+
+        let opts = Self::with_defaults(FdwOptions::for_user(&rel, userid));
+
+        let fdw_state = FdwState {
+            state: T::begin(&opts).unwrap_or_else(|err| err.report()),
+            itr: std::ptr::null_mut(),
+            relation: rinfo_box.ri_RelationDesc,
+            fdw_private: std::ptr::null_mut(),
+            param_exprs: std::ptr::null_mut(),
+            rescanned: false,
+            column_opts: opts.column_opts.clone(),
+            partitions: 0,
+            next_partition: 0,
+            parallel: std::ptr::null_mut(),
+            modified: false,
+            batch_size: if inserting {
+                Self::batch_size(&opts, rinfo)
+            } else {
+                1
+            },
+            batch: vec![],
+        };
 
-        rinfo_box.ri_FdwState = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
+        // Dropped when the executor's memory is released, on errors as well. `COPY` begins
+        // inserts outside of the executor's memory context.
+        rinfo_box.ri_FdwState = PgMemoryContexts::For((*estate).es_query_cxt)
+            .leak_and_drop_on_delete(fdw_state)
+            as pgx::memcxt::void_mut_ptr;
     }
 
     extern "C" fn exec_foreign_insert(
-        _estate: *mut EState,
+        estate: *mut EState,
         rinfo: *mut ResultRelInfo,
         slot: *mut TupleTableSlot,
         _plan_slot: *mut TupleTableSlot,
     ) -> *mut TupleTableSlot {
-        let mut rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
-        let slot_box = PgBox::<TupleTableSlot>::from_pg(slot);
-        let fdw_state = PgBox::<Self>::from_pg(rinfo_box.ri_FdwState as *mut Self);
-        let tupdesc = PgTupleDesc::from_pg_copy(slot_box.tts_tupleDescriptor);
+        pg_sys::guard::guard(move || {
+            let mut rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
+            let slot_box = PgBox::<TupleTableSlot>::from_pg(slot);
+            let mut fdw_state = PgBox::<Self>::from_pg(rinfo_box.ri_FdwState as *mut Self);
+            let tupdesc = PgTupleDesc::from_pg_copy(slot_box.tts_tupleDescriptor);
+
+            if fdw_state.batch_size > 1 {
+                // The slot is reused for the next row, the buffered row outlives it
+                let tuple = unsafe {
+                    PgMemoryContexts::For((*estate).es_query_cxt).switch_to(|_| {
+                        (*slot_box.tts_ops).copy_heap_tuple.unwrap()(slot_box.as_ptr())
+                    })
+                };
+
+                fdw_state.batch.push(tuple);
+
+                if fdw_state.batch.len() >= fdw_state.batch_size {
+                    fdw_state.flush_batch();
+                }
+
+                rinfo_box.ri_FdwState = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
+
+                return slot_box.into_pg();
+            }
+
+            let tuples = Self::slot_to_tuples(&slot_box, &tupdesc);
+
+            let result = fdw_state
+                .state
+                .insert(&tupdesc, &fdw_state.column_opts, tuples)
+                .unwrap_or_else(|err| err.report());
 
-        let tuples = Self::slot_to_tuples(&slot_box, &tupdesc);
+            rinfo_box.ri_FdwState = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
 
-        let _result = fdw_state.state.insert(&tupdesc, tuples);
+            match result {
+                Some(returned) => {
+                    let inserted = Self::slot_to_tuples(&slot_box, &tupdesc);
 
-        rinfo_box.ri_FdwState = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
-        slot_box.into_pg()
+                    Self::store_returned(slot, &tupdesc, inserted, returned)
+                }
+                None => slot_box.into_pg(),
+            }
+        })
+    }
+
+    /// Stores the tuples returned by `insert`, `update` or `delete` in `slot`, on top of
+    /// `current` for the columns that aren't returned
+    fn store_returned(
+        slot: *mut TupleTableSlot,
+        tupdesc: &PgTupleDesc,
+        current: Vec<Tuple>,
+        returned: Vec<Tuple>,
+    ) -> *mut TupleTableSlot {
+        let natts = tupdesc.len();
+        let mut nulls = vec![true; natts];
+        let mut datums = vec![0 as pg_sys::Datum; natts];
+
+        for (name, datum, _) in current.into_iter().chain(returned) {
+            if let Some(i) = tupdesc.iter().position(|attr| attr.name() == name) {
+                datums[i] = datum.unwrap_or(0);
+                nulls[i] = datum.is_none();
+            }
+        }
+
+        // The slot may still own the current values, so they're copied before it's cleared
+        unsafe {
+            let tuple =
+                pg_sys::heap_form_tuple(tupdesc.as_ptr(), datums.as_mut_ptr(), nulls.as_mut_ptr());
+
+            pg_sys::ExecForceStoreHeapTuple(tuple, slot, false);
+        }
+
+        slot
     }
 
     fn slot_to_tuples(slot: &PgBox<TupleTableSlot>, tupdesc: &PgTupleDesc) -> Vec<Tuple> {
@@ -339,6 +2335,14 @@ impl<T: ForeignData> FdwState<T> {
             )
         };
 
+        Self::values_to_tuples(tupdesc, datums, nulls)
+    }
+
+    fn values_to_tuples(
+        tupdesc: &PgTupleDesc,
+        datums: &[pg_sys::Datum],
+        nulls: &[bool],
+    ) -> Vec<Tuple> {
         let tuples: Vec<Tuple> = tupdesc
             .iter()
             .enumerate()
@@ -367,21 +2371,34 @@ impl<T: ForeignData> FdwState<T> {
         slot: *mut TupleTableSlot,
         plan_slot: *mut TupleTableSlot,
     ) -> *mut TupleTableSlot {
-        let mut rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
-        let fdw_state = PgBox::<Self>::from_pg(rinfo_box.ri_FdwState as *mut Self);
-        let slot_box = PgBox::<TupleTableSlot>::from_pg(slot);
-        let plan_slot_box = PgBox::<TupleTableSlot>::from_pg(plan_slot);
+        pg_sys::guard::guard(move || {
+            let mut rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
+            let fdw_state = PgBox::<Self>::from_pg(rinfo_box.ri_FdwState as *mut Self);
+            let slot_box = PgBox::<TupleTableSlot>::from_pg(slot);
+            let plan_slot_box = PgBox::<TupleTableSlot>::from_pg(plan_slot);
+
+            let tupdesc = PgTupleDesc::from_pg_copy(slot_box.tts_tupleDescriptor);
+            let plan_tupdesc = PgTupleDesc::from_pg_copy(plan_slot_box.tts_tupleDescriptor);
 
-        let tupdesc = PgTupleDesc::from_pg_copy(slot_box.tts_tupleDescriptor);
-        let plan_tupdesc = PgTupleDesc::from_pg_copy(plan_slot_box.tts_tupleDescriptor);
+            let tuples = Self::slot_to_tuples(&slot_box, &tupdesc);
+            let indices = Self::slot_to_tuples(&plan_slot_box, &plan_tupdesc);
 
-        let tuples = Self::slot_to_tuples(&slot_box, &tupdesc);
-        let indices = Self::slot_to_tuples(&plan_slot_box, &plan_tupdesc);
+            let result = fdw_state
+                .state
+                .update(&tupdesc, &fdw_state.column_opts, tuples, indices)
+                .unwrap_or_else(|err| err.report());
 
-        let _result = fdw_state.state.update(&tupdesc, tuples, indices);
+            rinfo_box.ri_FdwState = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
 
-        rinfo_box.ri_FdwState = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
-        slot_box.into_pg()
+            match result {
+                Some(returned) => {
+                    let updated = Self::slot_to_tuples(&slot_box, &tupdesc);
+
+                    Self::store_returned(slot, &tupdesc, updated, returned)
+                }
+                None => slot_box.into_pg(),
+            }
+        })
     }
 
     extern "C" fn exec_foreign_delete(
@@ -390,22 +2407,619 @@ impl<T: ForeignData> FdwState<T> {
         slot: *mut TupleTableSlot,
         plan_slot: *mut TupleTableSlot,
     ) -> *mut TupleTableSlot {
-        let mut rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
-        let fdw_state = PgBox::<Self>::from_pg(rinfo_box.ri_FdwState as *mut Self);
-        let plan_slot_box = PgBox::<TupleTableSlot>::from_pg(plan_slot);
+        pg_sys::guard::guard(move || {
+            let mut rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
+            let fdw_state = PgBox::<Self>::from_pg(rinfo_box.ri_FdwState as *mut Self);
+            let plan_slot_box = PgBox::<TupleTableSlot>::from_pg(plan_slot);
 
-        let tupdesc = PgTupleDesc::from_pg_copy(plan_slot_box.tts_tupleDescriptor);
+            let tupdesc = PgTupleDesc::from_pg_copy(plan_slot_box.tts_tupleDescriptor);
 
-        let tuples = Self::slot_to_tuples(&plan_slot_box, &tupdesc);
-        let _result = fdw_state.state.delete(&tupdesc, tuples);
+            let tuples = Self::slot_to_tuples(&plan_slot_box, &tupdesc);
+            let result = fdw_state
+                .state
+                .delete(&tupdesc, &fdw_state.column_opts, tuples)
+                .unwrap_or_else(|err| err.report());
 
-        rinfo_box.ri_FdwState = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
+            rinfo_box.ri_FdwState = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
 
-        slot
+            // `slot` is empty, only the returned columns are reported
+            match result {
+                Some(returned) => {
+                    let desc = PgTupleDesc::from_pg_copy(unsafe { (*slot).tts_tupleDescriptor });
+
+                    Self::store_returned(slot, &desc, vec![], returned)
+                }
+                None => slot,
+            }
+        })
+    }
+
+    extern "C" fn end_foreign_modify(_estate: *mut EState, rinfo: *mut ResultRelInfo) {
+        pg_sys::guard::guard(move || {
+            let rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
+
+            if rinfo_box.ri_FdwState.is_null() {
+                return;
+            }
+
+            let mut fdw_state = PgBox::<Self>::from_pg(rinfo_box.ri_FdwState as *mut Self);
+
+            fdw_state.flush_batch();
+            fdw_state
+                .state
+                .end_modify()
+                .unwrap_or_else(|err| err.report());
+        })
+    }
+
+    /// Explains scans and direct modifications as planned, runtime values of the quals aren't
+    /// computed
+    unsafe extern "C" fn explain_foreign_scan(node: *mut ForeignScanState, es: *mut ExplainState) {
+        pg_sys::guard::guard(move || {
+            let n = PgBox::<ForeignScanState>::from_pg(node);
+            let fdw_state = PgBox::<Self>::from_pg(n.fdw_state as *mut Self);
+            let plan = n.ss.ps.plan as *mut ForeignScan;
+            let fdw_private = fdw_state.fdw_private;
+            let tupdesc = PgTupleDesc::from_pg_copy((*fdw_state.relation).rd_att);
+            let scan = Self::planned_scan(
+                fdw_private,
+                &tupdesc,
+                &fdw_state.column_opts,
+                &mut std::iter::empty(),
+            );
+            let aggregation = Self::fdw_private_list(fdw_private, FDW_PRIVATE_AGGREGATION);
+            let join = Self::fdw_private_list(fdw_private, FDW_PRIVATE_JOIN);
+
+            let explained = if let Some(kind) = ModifyKind::from_pg((*plan).operation) {
+                let direct_modify = Self::planned_direct_modify(
+                    kind,
+                    fdw_private,
+                    &tupdesc,
+                    &mut std::iter::empty(),
+                );
+
+                fdw_state.state.explain(
+                    &Planned::DirectModify(&direct_modify),
+                    &mut Explain::new(es),
+                )
+            } else if !join.is_null() {
+                let join = Self::scan_join(
+                    join,
+                    fdw_state.relation,
+                    fdw_private,
+                    n.ss.ps.state,
+                    scan,
+                    &mut std::iter::empty(),
+                );
+
+                fdw_state
+                    .state
+                    .explain(&Planned::Join(&join), &mut Explain::new(es))
+            } else if !aggregation.is_null() {
+                match Aggregation::from_scan_tlist(aggregation, &tupdesc) {
+                    Some(aggregation) => fdw_state.state.explain(
+                        &Planned::Aggregate {
+                            scan: &scan,
+                            aggregation: &aggregation,
+                        },
+                        &mut Explain::new(es),
+                    ),
+                    None => error!("Unsupported aggregation in foreign scan"),
+                }
+            } else {
+                fdw_state
+                    .state
+                    .explain(&Planned::Scan(&scan), &mut Explain::new(es))
+            };
+
+            explained.unwrap_or_else(|err| err.report());
+        })
+    }
+
+    extern "C" fn explain_foreign_modify(
+        mtstate: *mut ModifyTableState,
+        rinfo: *mut ResultRelInfo,
+        _fdw_private: *mut List,
+        _subplan_index: ::std::os::raw::c_int,
+        es: *mut ExplainState,
+    ) {
+        pg_sys::guard::guard(move || {
+            let rinfo_box = PgBox::<ResultRelInfo>::from_pg(rinfo);
+
+            if rinfo_box.ri_FdwState.is_null() {
+                return;
+            }
+
+            let fdw_state = PgBox::<Self>::from_pg(rinfo_box.ri_FdwState as *mut Self);
+            let mut explain = Explain::new(es);
+
+            if explain.verbose() && fdw_state.batch_size > 1 {
+                explain.property_integer("Batch Size", None, fdw_state.batch_size as i64);
+            }
+
+            let planned = match unsafe { (*mtstate).operation } {
+                operation if operation == pg_sys::CmdType_CMD_INSERT => Planned::Insert,
+                operation if operation == pg_sys::CmdType_CMD_UPDATE => Planned::Update,
+                _ => Planned::Delete,
+            };
+
+            fdw_state
+                .state
+                .explain(&planned, &mut explain)
+                .unwrap_or_else(|err| err.report());
+        })
+    }
+
+    /// Takes over an `UPDATE` or `DELETE` whose rows are all found by a scan of the modified
+    /// table with every qual pushed down, when `ForeignData::supports_direct_modify` accepts
+    /// it. The scan then executes the statement with `ForeignData::modify`.
+    unsafe extern "C" fn plan_direct_modify(
+        root: *mut PlannerInfo,
+        plan: *mut ModifyTable,
+        result_relation: Index,
+        subplan_index: ::std::os::raw::c_int,
+    ) -> bool {
+        pg_sys::guard::guard(move || {
+            let kind = match ModifyKind::from_pg((*plan).operation) {
+                Some(kind) => kind,
+                None => return false,
+            };
+            let subplan =
+                match PgList::<Plan>::from_pg((*plan).plans).get_ptr(subplan_index as usize) {
+                    Some(subplan) if is_a(subplan as *mut Node, pg_sys::NodeTag_T_ForeignScan) => {
+                        subplan
+                    }
+                    _ => return false,
+                };
+            let fscan = subplan as *mut ForeignScan;
+
+            // Joined rows and quals checked by Postgres need the rows to be fetched
+            if (*fscan).scan.scanrelid != result_relation || !(*subplan).qual.is_null() {
+                return false;
+            }
+
+            let rte = *(*root).simple_rte_array.add(result_relation as usize);
+            let rel = PgRelation::with_lock((*rte).relid, pg_sys::NoLock as pg_sys::LOCKMODE);
+            let opts = Self::options(&rel);
+            let tupdesc = rel.tuple_desc();
+            let mut params = PgList::<Expr>::from_pg((*fscan).fdw_exprs);
+
+            let (assignments, attnums, exprs) = match kind {
+                ModifyKind::Update => match modify::plan_assignments(
+                    result_relation,
+                    &tupdesc,
+                    (*subplan).targetlist,
+                    (*rte).updatedCols,
+                    &mut params,
+                ) {
+                    Some(planned) => planned,
+                    None => return false,
+                },
+                ModifyKind::Delete => (vec![], std::ptr::null_mut(), std::ptr::null_mut()),
+            };
+            let quals = qual::from_remote_conds(
+                result_relation,
+                &tupdesc,
+                Self::fdw_private_list((*fscan).fdw_private, FDW_PRIVATE_REMOTE_CONDS),
+                &mut std::iter::empty(),
+            );
+            let returning = !(*plan).returningLists.is_null();
+
+            let direct_modify = DirectModify {
+                kind,
+                quals,
+                assignments,
+                returning,
+            };
+
+            if !T::supports_direct_modify(&opts, &direct_modify) {
+                return false;
+            }
+
+            let mut modify_private = PgList::<std::os::raw::c_void>::new();
+
+            modify_private.push(attnums as pgx::memcxt::void_mut_ptr);
+            modify_private.push(exprs as pgx::memcxt::void_mut_ptr);
+            modify_private.push(pg_sys::makeInteger(returning as _) as pgx::memcxt::void_mut_ptr);
+            modify_private
+                .push(pg_sys::makeInteger((*plan).canSetTag as _) as pgx::memcxt::void_mut_ptr);
+
+            // Base relation scans have no `FDW_PRIVATE_JOIN`
+            let mut fdw_private = PgList::<std::os::raw::c_void>::from_pg((*fscan).fdw_private);
+
+            fdw_private.push(std::ptr::null_mut());
+            fdw_private.push(modify_private.into_pg() as pgx::memcxt::void_mut_ptr);
+
+            (*fscan).operation = (*plan).operation;
+            (*fscan).fdw_exprs = params.into_pg();
+            (*fscan).fdw_private = fdw_private.into_pg();
+
+            true
+        })
+    }
+
+    /// Executes the statement on the first call, then returns the rows `RETURNING` needs.
+    /// Without `RETURNING` the modified rows are only counted.
+    unsafe extern "C" fn iterate_direct_modify(node: *mut ForeignScanState) -> *mut TupleTableSlot {
+        pg_sys::guard::guard(move || {
+            let mut n = PgBox::<ForeignScanState>::from_pg(node);
+            let mut fdw_state = PgBox::<Self>::from_pg(n.fdw_state as *mut Self);
+            let estate = n.ss.ps.state;
+            let rinfo = (*estate).es_result_relation_info;
+            let tupdesc = PgTupleDesc::from_pg_copy((*fdw_state.relation).rd_att);
+            let slot = Self::exec_clear_tuple(n.ss.ss_ScanTupleSlot);
+
+            if !fdw_state.modified {
+                let modify_private =
+                    Self::fdw_private_list(fdw_state.fdw_private, FDW_PRIVATE_MODIFY);
+                let kind =
+                    match ModifyKind::from_pg((*(n.ss.ps.plan as *mut ForeignScan)).operation) {
+                        Some(kind) => kind,
+                        None => error!("Unsupported direct modification"),
+                    };
+                let (count, itr) = Self::execute_direct_modify(
+                    kind,
+                    &mut fdw_state,
+                    &tupdesc,
+                    n.ss.ps.ps_ExprContext,
+                );
+
+                if Self::fdw_private_int(modify_private, FDW_MODIFY_PRIVATE_SET_PROCESSED) != 0 {
+                    (*estate).es_processed += count;
+                }
+
+                // Returned rows are counted as they're returned
+                if (*rinfo).ri_projectReturning.is_null() && !n.ss.ps.instrument.is_null() {
+                    (*n.ss.ps.instrument).tuplecount += count as f64;
+                }
+
+                if let Some(itr) = itr {
+                    fdw_state.itr = Box::into_raw(Box::new(itr));
+                }
+
+                fdw_state.modified = true;
+            }
+
+            let row = if (*rinfo).ri_projectReturning.is_null() || fdw_state.itr.is_null() {
+                None
+            } else {
+                (*fdw_state.itr).next()
+            };
+
+            n.fdw_state = fdw_state.into_pg() as pgx::memcxt::void_mut_ptr;
+
+            match row {
+                Some(row) => {
+                    let positions = (0..tupdesc.len()).collect::<Vec<_>>();
+                    let attnums = (1..=tupdesc.len()).collect::<Vec<_>>();
+                    let slot =
+                        Self::store_tuple(slot, Some(tupdesc.len()), &attnums, &positions, row);
+
+                    // The `RETURNING` list is computed from the modified row
+                    (*(*(*rinfo).ri_projectReturning).pi_exprContext).ecxt_scantuple = slot;
+
+                    slot
+                }
+                None => slot,
+            }
+        })
+    }
+
+    unsafe fn execute_direct_modify(
+        kind: ModifyKind,
+        fdw_state: &mut PgBox<FdwState<T>>,
+        tupdesc: &PgTupleDesc,
+        econtext: *mut ExprContext,
+    ) -> (u64, Option<T::RowIterator>) {
+        let mut values = Self::param_values(fdw_state, econtext).into_iter();
+        let direct_modify =
+            Self::planned_direct_modify(kind, fdw_state.fdw_private, tupdesc, &mut values);
+
+        let FdwState {
+            state, column_opts, ..
+        } = &mut **fdw_state;
+
+        state
+            .modify(tupdesc, column_opts, &direct_modify)
+            .unwrap_or_else(|err| err.report())
+    }
+
+    /// The modification planned by `plan_direct_modify`, with the `QualValue::Param`s taken
+    /// from `values`
+    unsafe fn planned_direct_modify(
+        kind: ModifyKind,
+        fdw_private: *mut List,
+        tupdesc: &PgTupleDesc,
+        values: &mut impl Iterator<Item = Option<pg_sys::Datum>>,
+    ) -> DirectModify {
+        let modify_private = Self::fdw_private_list(fdw_private, FDW_PRIVATE_MODIFY);
+        let relid = Self::fdw_private_int(fdw_private, FDW_PRIVATE_RELID) as Index;
+
+        // Values of the quals come first, as planned by `plan_direct_modify`
+        let quals = qual::from_remote_conds(
+            relid,
+            tupdesc,
+            Self::fdw_private_list(fdw_private, FDW_PRIVATE_REMOTE_CONDS),
+            values,
+        );
+        let assignments = modify::from_assignments(
+            relid,
+            tupdesc,
+            Self::fdw_private_list(modify_private, FDW_MODIFY_PRIVATE_ATTNUMS),
+            Self::fdw_private_list(modify_private, FDW_MODIFY_PRIVATE_ASSIGNMENTS),
+            values,
+        );
+
+        DirectModify {
+            kind,
+            quals,
+            assignments,
+            returning: Self::fdw_private_int(modify_private, FDW_MODIFY_PRIVATE_RETURNING) != 0,
+        }
+    }
+
+    unsafe extern "C" fn end_direct_modify(node: *mut ForeignScanState) {
+        pg_sys::guard::guard(move || {
+            let n = PgBox::<ForeignScanState>::from_pg(node);
+
+            if n.fdw_state.is_null() {
+                return;
+            }
+
+            let mut fdw_state = PgBox::<Self>::from_pg(n.fdw_state as *mut Self);
+
+            fdw_state.drop_iterator();
+            fdw_state
+                .state
+                .end_modify()
+                .unwrap_or_else(|err| err.report());
+        })
+    }
+
+    unsafe extern "C" fn is_foreign_scan_parallel_safe(
+        _root: *mut PlannerInfo,
+        _rel: *mut RelOptInfo,
+        rte: *mut RangeTblEntry,
+    ) -> bool {
+        pg_sys::guard::guard(move || {
+            let rel = PgRelation::with_lock((*rte).relid, pg_sys::NoLock as pg_sys::LOCKMODE);
+
+            T::parallel_safe(&Self::options(&rel))
+        })
+    }
+
+    extern "C" fn estimate_dsm_foreign_scan(
+        _node: *mut ForeignScanState,
+        _pcxt: *mut ParallelContext,
+    ) -> Size {
+        std::mem::size_of::<ParallelScan>()
+    }
+
+    unsafe extern "C" fn initialize_dsm_foreign_scan(
+        node: *mut ForeignScanState,
+        _pcxt: *mut ParallelContext,
+        coordinate: *mut ::std::os::raw::c_void,
+    ) {
+        pg_sys::guard::guard(move || {
+            let fdw_state = (*node).fdw_state as *mut Self;
+            let parallel = coordinate as *mut ParallelScan;
+
+            ParallelScan::init(parallel, (*fdw_state).partitions);
+            (*fdw_state).parallel = parallel;
+        })
+    }
+
+    unsafe extern "C" fn re_initialize_dsm_foreign_scan(
+        node: *mut ForeignScanState,
+        _pcxt: *mut ParallelContext,
+        coordinate: *mut ::std::os::raw::c_void,
+    ) {
+        pg_sys::guard::guard(move || {
+            let fdw_state = (*node).fdw_state as *mut Self;
+            let parallel = coordinate as *mut ParallelScan;
+
+            (*parallel).reset();
+            (*fdw_state).parallel = parallel;
+        })
+    }
+
+    unsafe extern "C" fn initialize_worker_foreign_scan(
+        node: *mut ForeignScanState,
+        _toc: *mut shm_toc,
+        coordinate: *mut ::std::os::raw::c_void,
+    ) {
+        pg_sys::guard::guard(move || {
+            let fdw_state = (*node).fdw_state as *mut Self;
+            let parallel = coordinate as *mut ParallelScan;
+
+            // Use the leader's partition count, in case the worker's `begin` sees different ones
+            (*fdw_state).partitions = (*parallel).partitions as usize;
+            (*fdw_state).parallel = parallel;
+        })
+    }
+
+    unsafe extern "C" fn shutdown_foreign_scan(node: *mut ForeignScanState) {
+        pg_sys::guard::guard(move || {
+            let fdw_state = (*node).fdw_state as *mut Self;
+
+            // The shared memory is released after this
+            if !fdw_state.is_null() {
+                (*fdw_state).parallel = std::ptr::null_mut();
+            }
+        })
+    }
+
+    extern "C" fn analyze_foreign_table(
+        _relation: Relation,
+        func: *mut AcquireSampleRowsFunc,
+        totalpages: *mut BlockNumber,
+    ) -> bool {
+        pg_sys::guard::guard(move || {
+            unsafe {
+                *func = Some(Self::acquire_sample_rows);
+                // Rows aren't stored in pages, but Postgres expects a table to have some
+                *totalpages = 1;
+            }
+
+            true
+        })
+    }
+
+    unsafe extern "C" fn acquire_sample_rows(
+        relation: Relation,
+        _elevel: ::std::os::raw::c_int,
+        rows: *mut HeapTuple,
+        targrows: ::std::os::raw::c_int,
+        totalrows: *mut f64,
+        totaldeadrows: *mut f64,
+    ) -> ::std::os::raw::c_int {
+        pg_sys::guard::guard(move || {
+            let rel = PgRelation::from_pg(relation);
+            let opts = Self::options(&rel);
+            let tupdesc = PgTupleDesc::from_pg_copy((*relation).rd_att);
+            let target_rows = targrows as usize;
+            let (attnums, columns): (Vec<usize>, Vec<String>) = tupdesc
+                .iter()
+                .filter(|attr| !attr.is_dropped())
+                .map(|attr| (attr.attnum as usize, attr.name().into()))
+                .unzip();
+
+            let mut state = T::begin(&opts).unwrap_or_else(|err| err.report());
+            let (sample, total) = match state
+                .sample(&tupdesc, target_rows)
+                .unwrap_or_else(|err| err.report())
+            {
+                Some((itr, total)) => (itr.take(target_rows).collect(), total),
+                None => {
+                    let itr = state
+                        .execute(&Scan {
+                            desc: &tupdesc,
+                            column_opts: &opts.column_opts,
+                            quals: vec![],
+                            columns,
+                            sort: vec![],
+                            limit: None,
+                        })
+                        .unwrap_or_else(|err| err.report());
+
+                    Self::reservoir_sample(itr, target_rows)
+                }
+            };
+
+            state.end_scan().unwrap_or_else(|err| err.report());
+
+            let natts = tupdesc.len();
+            let sampled = sample.len();
+
+            for (i, row) in sample.into_iter().enumerate() {
+                *rows.add(i) = Self::form_tuple(relation, natts, &attnums, row);
+            }
+
+            *totalrows = total;
+            *totaldeadrows = 0.0;
+
+            sampled as ::std::os::raw::c_int
+        })
+    }
+
+    /// Algorithm R, keeping a uniformly random sample of `target_rows` rows of `itr`. Returns
+    /// the sample and the number of rows of `itr`.
+    unsafe fn reservoir_sample(
+        itr: T::RowIterator,
+        target_rows: usize,
+    ) -> (Vec<Vec<<T as ForeignData>::Item>>, f64) {
+        let mut sample = Vec::with_capacity(target_rows);
+        let mut seen = 0usize;
+
+        for row in itr {
+            if sample.len() < target_rows {
+                sample.push(row);
+            } else {
+                let k = (pg_sys::anl_random_fract() * (seen + 1) as f64) as usize;
+
+                if k < target_rows {
+                    sample[k] = row;
+                }
+            }
+
+            seen += 1;
+            pg_sys::vacuum_delay_point();
+        }
+
+        (sample, seen as f64)
+    }
+
+    /// A tuple of `relation` from a row with a value for every non-dropped column, or for
+    /// every column
+    unsafe fn form_tuple(
+        relation: Relation,
+        natts: usize,
+        attnums: &[usize],
+        row: Vec<<T as ForeignData>::Item>,
+    ) -> HeapTuple {
+        let mut nulls = vec![true; natts];
+        let mut datums = vec![0 as pg_sys::Datum; natts];
+        let full_row = row.len() == natts;
+
+        for (i, item) in row.into_iter().enumerate() {
+            let position = if full_row {
+                i
+            } else {
+                match attnums.get(i) {
+                    Some(attnum) => attnum - 1,
+                    None => break,
+                }
+            };
+
+            if let Some(datum) = item.into_datum() {
+                datums[position] = datum;
+                nulls[position] = false;
+            }
+        }
+
+        pg_sys::heap_form_tuple((*relation).rd_att, datums.as_mut_ptr(), nulls.as_mut_ptr())
     }
 
-    extern "C" fn end_foreign_modify(_estate: *mut EState, _rinfo: *mut ResultRelInfo) {}
+    extern "C" fn import_foreign_schema(
+        stmt: *mut ImportForeignSchemaStmt,
+        server_oid: Oid,
+    ) -> *mut List {
+        pg_sys::guard::guard(move || {
+            let import = unsafe { ImportSchema::from_pg(stmt, server_oid) };
+            let tables = T::import_schema(&import).unwrap_or_else(|err| err.report());
+            let mut commands = PgList::<std::os::raw::c_char>::new();
+
+            for table in tables {
+                let sql = std::ffi::CString::new(table.to_sql(&import.server_name)).unwrap_or_else(
+                    |_| error!("CREATE FOREIGN TABLE of {} has a nul byte", table.name),
+                );
+
+                commands.push(unsafe { pg_sys::pstrdup(sql.as_ptr()) });
+            }
+
+            commands.into_pg()
+        })
+    }
+
+    /// Validates the options of `catalog` (the `text[]` and `oid` arguments of a validator
+    /// function) against `ForeignData::option_schema`, raising an error for unknown, missing
+    /// or malformed options.
+    pub fn validate(options: Vec<Option<String>>, catalog: pg_sys::Oid) {
+        let catalog = match OptionCatalog::from_oid(catalog) {
+            Some(catalog) => catalog,
+            None => return,
+        };
+        let options: Vec<String> = options.into_iter().flatten().collect();
+        let mut schema = options::builtin_schema();
+
+        schema.extend(T::option_schema());
+
+        options::validate(&schema, &options, catalog).unwrap_or_else(|err| err.report());
+    }
 
+    /// The `FdwRoutine` of `T`. Its callbacks run under `pg_sys::guard::guard`, which turns
+    /// panics and errors reported with `ereport` into an `ERROR` instead of unwinding into
+    /// Postgres.
     pub fn into_datum() -> pg_sys::Datum {
         let mut handler = PgBox::<pg_sys::FdwRoutine>::alloc_node(pg_sys::NodeTag_T_FdwRoutine);
 
@@ -416,12 +3030,14 @@ impl<T: ForeignData> FdwState<T> {
         handler.IterateForeignScan = Some(Self::iterate_foreign_scan);
         handler.ReScanForeignScan = Some(Self::re_scan_foreign_scan);
         handler.EndForeignScan = Some(Self::end_foreign_scan);
-        handler.EndForeignInsert = None;
+        handler.BeginForeignInsert = Some(Self::begin_foreign_insert);
+        // Inserts end like other modifications, with the last batch inserted
+        handler.EndForeignInsert = Some(Self::end_foreign_modify);
         handler.ReparameterizeForeignPathByChild = None;
-        handler.ShutdownForeignScan = None;
-        handler.ReInitializeDSMForeignScan = None;
-        handler.GetForeignJoinPaths = None;
-        handler.GetForeignUpperPaths = None;
+        handler.ShutdownForeignScan = Some(Self::shutdown_foreign_scan);
+        handler.ReInitializeDSMForeignScan = Some(Self::re_initialize_dsm_foreign_scan);
+        handler.GetForeignJoinPaths = Some(Self::get_foreign_join_paths);
+        handler.GetForeignUpperPaths = Some(Self::get_foreign_upper_paths);
         handler.AddForeignUpdateTargets = Some(Self::add_foreign_update_targets);
         handler.PlanForeignModify = None;
         handler.BeginForeignModify = Some(Self::begin_foreign_modify);
@@ -430,22 +3046,23 @@ impl<T: ForeignData> FdwState<T> {
         handler.ExecForeignDelete = Some(Self::exec_foreign_delete);
         handler.EndForeignModify = Some(Self::end_foreign_modify);
         handler.IsForeignRelUpdatable = None;
-        handler.PlanDirectModify = None;
-        handler.BeginDirectModify = None;
-        handler.IterateDirectModify = None;
-        handler.EndDirectModify = None;
+        handler.PlanDirectModify = Some(Self::plan_direct_modify);
+        // Direct modifications are scans of the modified table
+        handler.BeginDirectModify = Some(Self::begin_foreign_scan);
+        handler.IterateDirectModify = Some(Self::iterate_direct_modify);
+        handler.EndDirectModify = Some(Self::end_direct_modify);
         handler.GetForeignRowMarkType = None;
         handler.RefetchForeignRow = None;
         handler.RecheckForeignScan = None;
-        handler.ExplainForeignScan = None;
-        handler.ExplainForeignModify = None;
-        handler.ExplainDirectModify = None;
-        handler.AnalyzeForeignTable = None;
-        handler.ImportForeignSchema = None;
-        handler.IsForeignScanParallelSafe = None;
-        handler.EstimateDSMForeignScan = None;
-        handler.InitializeDSMForeignScan = None;
-        handler.InitializeWorkerForeignScan = None;
+        handler.ExplainForeignScan = Some(Self::explain_foreign_scan);
+        handler.ExplainForeignModify = Some(Self::explain_foreign_modify);
+        handler.ExplainDirectModify = Some(Self::explain_foreign_scan);
+        handler.AnalyzeForeignTable = Some(Self::analyze_foreign_table);
+        handler.ImportForeignSchema = Some(Self::import_foreign_schema);
+        handler.IsForeignScanParallelSafe = Some(Self::is_foreign_scan_parallel_safe);
+        handler.EstimateDSMForeignScan = Some(Self::estimate_dsm_foreign_scan);
+        handler.InitializeDSMForeignScan = Some(Self::initialize_dsm_foreign_scan);
+        handler.InitializeWorkerForeignScan = Some(Self::initialize_worker_foreign_scan);
 
         return handler.into_pg() as pg_sys::Datum;
     }
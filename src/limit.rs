@@ -0,0 +1,80 @@
+use pg_sys::*;
+use pgx::*;
+
+/// `LIMIT`/`OFFSET` of a query pushed down to `ForeignData::execute`. The rows returned must
+/// already have `offset` rows skipped, Postgres won't apply either of them again.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    /// `None` for `LIMIT ALL`
+    pub count: Option<i64>,
+    pub offset: i64,
+}
+
+impl Limit {
+    /// The query's `LIMIT`/`OFFSET`, if both are constants
+    pub(crate) unsafe fn from_query(parse: *mut Query) -> Option<Self> {
+        let count = Self::const_value((*parse).limitCount)?;
+        let offset = Self::const_value((*parse).limitOffset)?.unwrap_or(0);
+
+        // Leave negative values for Postgres to report
+        if matches!(count, Some(count) if count < 0) || offset < 0 {
+            return None;
+        }
+
+        Some(Limit { count, offset })
+    }
+
+    unsafe fn const_value(node: *mut Node) -> Option<Option<i64>> {
+        if node.is_null() {
+            return Some(None);
+        }
+
+        if !is_a(node, NodeTag_T_Const) {
+            return None;
+        }
+
+        let constant = node as *mut Const;
+
+        if (*constant).constisnull {
+            Some(None)
+        } else {
+            Some(Some((*constant).constvalue as i64))
+        }
+    }
+
+    /// Encodes the limit as a `List` of two int8 `Const`s, so it can be stored in `fdw_private`
+    pub(crate) unsafe fn to_list(self) -> *mut List {
+        let mut list = PgList::<Const>::new();
+
+        list.push(Self::make_const(self.count));
+        list.push(Self::make_const(Some(self.offset)));
+        list.into_pg()
+    }
+
+    unsafe fn make_const(value: Option<i64>) -> *mut Const {
+        makeConst(
+            INT8OID,
+            -1,
+            InvalidOid,
+            8,
+            value.map_or(0, |value| value as pg_sys::Datum),
+            value.is_none(),
+            true,
+        )
+    }
+
+    /// Decodes a `List` created by `to_list`
+    pub(crate) unsafe fn from_list(list: *mut List) -> Option<Self> {
+        let list = PgList::<Const>::from_pg(list);
+        let (count, offset) = (list.get_ptr(0)?, list.get_ptr(1)?);
+
+        Some(Limit {
+            count: if (*count).constisnull {
+                None
+            } else {
+                Some((*count).constvalue as i64)
+            },
+            offset: (*offset).constvalue as i64,
+        })
+    }
+}
@@ -0,0 +1,116 @@
+use crate::qual::{self, Deparser};
+use crate::{Qual, QualValue};
+use pg_sys::*;
+use pgx::*;
+
+/// An `UPDATE` or `DELETE` executed in a single operation by `ForeignData::modify`, instead of
+/// fetching the rows and modifying them one at a time.
+#[derive(Debug, Clone)]
+pub struct DirectModify {
+    pub kind: ModifyKind,
+    /// The rows to modify, every qual of the statement's `WHERE` clause
+    pub quals: Vec<Qual>,
+    /// `SET column = value` of an `UPDATE`, empty for a `DELETE`
+    pub assignments: Vec<Assignment>,
+    /// Whether the statement has a `RETURNING` clause that needs the modified rows
+    pub returning: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModifyKind {
+    Update,
+    Delete,
+}
+
+/// `column = value` of an `UPDATE`. Values computed from the modified row, e.g.
+/// `count = count + 1`, can't be pushed down.
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub column: String,
+    pub value: QualValue,
+}
+
+impl ModifyKind {
+    pub(crate) fn from_pg(operation: CmdType) -> Option<Self> {
+        match operation {
+            operation if operation == CmdType_CMD_UPDATE => Some(ModifyKind::Update),
+            operation if operation == CmdType_CMD_DELETE => Some(ModifyKind::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Assignments of the columns `SET` by an `UPDATE`, with the attribute numbers and expressions
+/// to store in `fdw_private`. `None` when any of them can't be pushed down. Runtime values are
+/// appended to `params`.
+pub(crate) unsafe fn plan_assignments(
+    relid: Index,
+    desc: &PgTupleDesc,
+    tlist: *mut List,
+    updated_cols: *mut Bitmapset,
+    params: &mut PgList<Expr>,
+) -> Option<(Vec<Assignment>, *mut List, *mut List)> {
+    let mut assignments = vec![];
+    let mut attnums: *mut List = std::ptr::null_mut();
+    let mut exprs = PgList::<Expr>::new();
+    let mut assignment_params = vec![];
+    let mut deparser = Deparser::new(relid, desc, |expr| {
+        assignment_params.push(expr);
+        QualValue::Param(None, PgOid::from(exprType(expr as *mut Node)))
+    });
+    let mut member = bms_next_member(updated_cols, -1);
+
+    while member >= 0 {
+        let attnum = member + FirstLowInvalidHeapAttributeNumber;
+        let tle = get_tle_by_resno(tlist, attnum as AttrNumber);
+
+        // System columns can't be updated
+        if attnum <= 0 || tle.is_null() {
+            return None;
+        }
+
+        let expr = qual::strip_relabel((*tle).expr);
+
+        assignments.push(Assignment {
+            column: desc.get(attnum as usize - 1)?.name().into(),
+            value: deparser.value(expr)?,
+        });
+        attnums = lappend_int(attnums, attnum);
+        exprs.push(expr);
+        member = bms_next_member(updated_cols, member);
+    }
+
+    assignment_params
+        .into_iter()
+        .for_each(|expr| params.push(expr));
+
+    Some((assignments, attnums, exprs.into_pg()))
+}
+
+/// Rebuilds the assignments planned by `plan_assignments` at execution time, filling in
+/// `QualValue::Param`s from `values`.
+pub(crate) unsafe fn from_assignments(
+    relid: Index,
+    desc: &PgTupleDesc,
+    attnums: *mut List,
+    exprs: *mut List,
+    values: &mut impl Iterator<Item = Option<pg_sys::Datum>>,
+) -> Vec<Assignment> {
+    let mut deparser = Deparser::new(relid, desc, |expr| {
+        QualValue::Param(
+            values.next().flatten(),
+            PgOid::from(exprType(expr as *mut Node)),
+        )
+    });
+
+    PgList::<std::os::raw::c_void>::from_pg(attnums)
+        .iter_int()
+        .zip(PgList::<Expr>::from_pg(exprs).iter_ptr())
+        .filter_map(|(attnum, expr)| {
+            Some(Assignment {
+                column: desc.get(attnum as usize - 1)?.name().into(),
+                value: deparser.value(expr)?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,576 @@
+use crate::{FdwError, FdwOption, FdwOptions, FdwResult};
+use pgx::*;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Not exported by `pg_sys`, from `pg_foreign_data_wrapper_d.h`, `pg_foreign_server_d.h`,
+// `pg_user_mapping_d.h` and `pg_foreign_table_d.h`
+const FOREIGN_DATA_WRAPPER_RELATION_ID: pg_sys::Oid = 2328;
+const FOREIGN_SERVER_RELATION_ID: pg_sys::Oid = 1417;
+const USER_MAPPING_RELATION_ID: pg_sys::Oid = 1418;
+const FOREIGN_TABLE_RELATION_ID: pg_sys::Oid = 3118;
+
+/// An option recognized by a `ForeignData` implementation, declared by
+/// `ForeignData::option_schema`, e.g.
+///
+/// ```rust,ignore
+/// OptionSpec::new("batch_size", OptionType::PositiveInteger, &[OptionCatalog::Server, OptionCatalog::Table])
+///     .with_default("100")
+/// ```
+#[derive(Debug, Clone)]
+pub struct OptionSpec {
+    pub name: String,
+    pub option_type: OptionType,
+    /// Objects the option may be given on
+    pub catalogs: Vec<OptionCatalog>,
+    /// Whether the option has to be given. Only checked by the validator for options valid on
+    /// a single kind of object, otherwise it may be given on any of them.
+    pub required: bool,
+    /// Value used when the option isn't given on any of `catalogs`
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionType {
+    Text,
+    Integer,
+    /// An integer above 0, e.g. a number of rows
+    PositiveInteger,
+    Float,
+    /// `true`/`false`, `on`/`off`, `yes`/`no` or `1`/`0`
+    Boolean,
+}
+
+/// The objects options are given on with `OPTIONS (...)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionCatalog {
+    Wrapper,
+    Server,
+    UserMapping,
+    Table,
+    Column,
+}
+
+impl OptionSpec {
+    pub fn new(
+        name: impl Into<String>,
+        option_type: OptionType,
+        catalogs: &[OptionCatalog],
+    ) -> Self {
+        OptionSpec {
+            name: name.into(),
+            option_type,
+            catalogs: catalogs.to_vec(),
+            required: false,
+            default: None,
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn with_default(mut self, default: impl Into<String>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    fn check_value(&self, value: &str) -> FdwResult<()> {
+        let valid = match self.option_type {
+            OptionType::Text => true,
+            OptionType::Integer => value.parse::<i64>().is_ok(),
+            OptionType::PositiveInteger => matches!(value.parse::<i64>(), Ok(value) if value > 0),
+            OptionType::Float => matches!(value.parse::<f64>(), Ok(value) if value.is_finite()),
+            OptionType::Boolean => parse_bool(value).is_some(),
+        };
+
+        if valid {
+            return Ok(());
+        }
+
+        let expected = match self.option_type {
+            OptionType::Text => "a text",
+            OptionType::Integer => "an integer",
+            OptionType::PositiveInteger => "a positive integer",
+            OptionType::Float => "a numeric",
+            OptionType::Boolean => "a boolean",
+        };
+
+        Err(FdwError::new(
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("option \"{}\" requires {} value", self.name, expected),
+        )
+        .with_detail(format!("Invalid value: \"{}\".", value)))
+    }
+}
+
+impl OptionCatalog {
+    pub(crate) fn from_oid(catalog: pg_sys::Oid) -> Option<Self> {
+        match catalog {
+            FOREIGN_DATA_WRAPPER_RELATION_ID => Some(OptionCatalog::Wrapper),
+            FOREIGN_SERVER_RELATION_ID => Some(OptionCatalog::Server),
+            USER_MAPPING_RELATION_ID => Some(OptionCatalog::UserMapping),
+            FOREIGN_TABLE_RELATION_ID => Some(OptionCatalog::Table),
+            pg_sys::AttributeRelationId => Some(OptionCatalog::Column),
+            _ => None,
+        }
+    }
+}
+
+/// Types read from options by `FdwOptions::get_value` and `#[derive(FromOptions)]`
+pub trait OptionValue: Sized {
+    /// Parses `value`, the error describes why it's invalid
+    fn parse_option(value: &str) -> Result<Self, String>;
+}
+
+/// Structs built from the options of a table, usually implemented with
+/// `#[derive(FromOptions)]`
+pub trait FromOptions: Sized {
+    fn from_options(options: &FdwOptions) -> FdwResult<Self>;
+}
+
+macro_rules! from_str_option_value {
+    ($($ty:ty),*) => {
+        $(
+            impl OptionValue for $ty {
+                fn parse_option(value: &str) -> Result<Self, String> {
+                    value.trim().parse().map_err(|err| format!("{}", err))
+                }
+            }
+        )*
+    };
+}
+
+from_str_option_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl OptionValue for String {
+    fn parse_option(value: &str) -> Result<Self, String> {
+        Ok(value.into())
+    }
+}
+
+impl OptionValue for bool {
+    fn parse_option(value: &str) -> Result<Self, String> {
+        parse_bool(value).ok_or_else(|| "expected true/false, on/off, yes/no or 1/0".into())
+    }
+}
+
+/// A number with an optional unit of `us`, `ms`, `s`, `min`, `h` or `d`, e.g. `30s`.
+/// Milliseconds without a unit, like Postgres' timeout settings.
+impl OptionValue for Duration {
+    fn parse_option(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let split = value
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(value.len());
+        let (number, unit) = value.split_at(split);
+
+        let number: f64 = number
+            .parse()
+            .map_err(|_| "expected a number followed by a unit, e.g. 30s".to_string())?;
+        let seconds = match unit.trim() {
+            "us" => number / 1_000_000.0,
+            "ms" | "" => number / 1000.0,
+            "s" => number,
+            "min" => number * 60.0,
+            "h" => number * 3600.0,
+            "d" => number * 86400.0,
+            unit => {
+                return Err(format!(
+                    "invalid unit \"{}\", valid units are us, ms, s, min, h and d",
+                    unit
+                ))
+            }
+        };
+
+        // `u64::MAX as f64` rounds up to 2^64 seconds, which is already out of range
+        if !seconds.is_finite() || seconds >= u64::MAX as f64 {
+            return Err("duration out of range".into());
+        }
+
+        Ok(Duration::from_secs_f64(seconds))
+    }
+}
+
+/// Comma separated values, surrounding whitespace and empty values are ignored
+impl OptionValue for Vec<String> {
+    fn parse_option(value: &str) -> Result<Self, String> {
+        Ok(value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect())
+    }
+}
+
+// Objects options are looked up on by `FdwOptions::value`, most specific first
+const PRECEDENCE: [OptionCatalog; 4] = [
+    OptionCatalog::Table,
+    OptionCatalog::UserMapping,
+    OptionCatalog::Server,
+    OptionCatalog::Wrapper,
+];
+
+impl FdwOptions {
+    /// The value of option `name`, from the options of the table, the user mapping, the
+    /// server or else the wrapper
+    pub fn value(&self, name: &str) -> Option<&str> {
+        PRECEDENCE
+            .iter()
+            .filter_map(|catalog| self.catalog_opts(*catalog))
+            .find_map(|opts| opts.get(name))
+            .map(String::as_str)
+    }
+
+    /// The value of option `name` of `column`, or else `value(name)`
+    pub fn column_value(&self, column: &str, name: &str) -> Option<&str> {
+        self.column_opts
+            .get(column)
+            .and_then(|opts| opts.get(name))
+            .map(String::as_str)
+            .or_else(|| self.value(name))
+    }
+
+    /// The options given on `catalog`, `None` for columns
+    pub fn catalog_opts(&self, catalog: OptionCatalog) -> Option<&FdwOption> {
+        match catalog {
+            OptionCatalog::Wrapper => Some(&self.wrapper_opts),
+            OptionCatalog::Server => Some(&self.server_opts),
+            OptionCatalog::UserMapping => Some(&self.user_opts),
+            OptionCatalog::Table => Some(&self.table_opts),
+            OptionCatalog::Column => None,
+        }
+    }
+
+    /// Option `name` parsed with `FromStr`, `None` when it isn't given
+    pub fn get<T>(&self, name: &str) -> FdwResult<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.value(name)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|err| invalid_value(name, value, err))
+            })
+            .transpose()
+    }
+
+    /// Option `name` parsed with `FromStr`, `default` when it isn't given
+    pub fn get_or<T>(&self, name: &str, default: T) -> FdwResult<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        Ok(self.get(name)?.unwrap_or(default))
+    }
+
+    /// Option `name` parsed with `FromStr`, an error when it isn't given
+    pub fn require<T>(&self, name: &str) -> FdwResult<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.get(name)?.ok_or_else(|| missing_option(name))
+    }
+
+    /// Option `name` as a boolean, accepting the same values as Postgres' `boolean`
+    pub fn get_bool(&self, name: &str) -> FdwResult<Option<bool>> {
+        self.get_value(name)
+    }
+
+    /// Option `name` as a duration, e.g. `500ms`, `30s` or `5min`
+    pub fn get_duration(&self, name: &str) -> FdwResult<Option<Duration>> {
+        self.get_value(name)
+    }
+
+    /// Option `name` as a comma separated list, e.g. `'a, b, c'`
+    pub fn get_list(&self, name: &str) -> FdwResult<Option<Vec<String>>> {
+        self.get_value(name)
+    }
+
+    /// Option `name` parsed with `OptionValue`, `None` when it isn't given
+    pub fn get_value<T: OptionValue>(&self, name: &str) -> FdwResult<Option<T>> {
+        self.value(name)
+            .map(|value| Self::parse_value(name, value))
+            .transpose()
+    }
+
+    /// Option `name` parsed with `OptionValue`, an error when it isn't given
+    pub fn require_value<T: OptionValue>(&self, name: &str) -> FdwResult<T> {
+        self.get_value(name)?.ok_or_else(|| missing_option(name))
+    }
+
+    #[doc(hidden)]
+    pub fn parse_value<T: OptionValue>(name: &str, value: &str) -> FdwResult<T> {
+        T::parse_option(value).map_err(|err| invalid_value(name, value, err))
+    }
+}
+
+fn invalid_value(name: &str, value: &str, err: impl Display) -> FdwError {
+    FdwError::new(
+        PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+        format!("invalid value for option \"{}\": \"{}\"", name, value),
+    )
+    .with_detail(format!("{}", err))
+}
+
+fn missing_option(name: &str) -> FdwError {
+    FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_OPTION_NAME_NOT_FOUND,
+        format!("option \"{}\" is required", name),
+    )
+}
+
+/// Options handled by pgx-fdw itself, valid in addition to `ForeignData::option_schema`
+pub(crate) fn builtin_schema() -> Vec<OptionSpec> {
+    vec![
+        OptionSpec::new("rows", OptionType::Float, &[OptionCatalog::Table]),
+        OptionSpec::new(
+            "batch_size",
+            OptionType::PositiveInteger,
+            &[OptionCatalog::Server, OptionCatalog::Table],
+        ),
+    ]
+}
+
+/// Checks `options` (`name=value` entries, as passed to a validator function) given on
+/// `catalog` against `schema`.
+pub(crate) fn validate(
+    schema: &[OptionSpec],
+    options: &[String],
+    catalog: OptionCatalog,
+) -> FdwResult<()> {
+    let valid: Vec<&OptionSpec> = schema
+        .iter()
+        .filter(|spec| spec.catalogs.contains(&catalog))
+        .collect();
+    let mut given = vec![];
+
+    for option in options {
+        let (name, value) = match option.find('=') {
+            Some(idx) => (&option[..idx], &option[idx + 1..]),
+            None => (option.as_str(), ""),
+        };
+
+        match valid.iter().find(|spec| spec.name == name) {
+            Some(spec) => spec.check_value(value)?,
+            None => return Err(unknown_option(name, &valid)),
+        }
+
+        given.push(name);
+    }
+
+    // Options valid on other objects as well may be given there instead
+    let missing = valid
+        .iter()
+        .find(|spec| spec.required && spec.catalogs == [catalog] && !given.contains(&&*spec.name));
+
+    match missing {
+        Some(spec) => Err(missing_option(&spec.name)),
+        None => Ok(()),
+    }
+}
+
+/// Fills in the defaults of options of `schema` that aren't given anywhere. The default is
+/// set on the most specific object the option is valid for, other than a column.
+pub(crate) fn apply_defaults(options: &mut FdwOptions, schema: &[OptionSpec]) {
+    for spec in schema {
+        let default = match &spec.default {
+            Some(default) => default,
+            None => continue,
+        };
+
+        let given = spec.catalogs.iter().any(|catalog| {
+            matches!(options.catalog_opts(*catalog), Some(opts) if opts.contains_key(&spec.name))
+        });
+
+        if given {
+            continue;
+        }
+
+        let catalog = PRECEDENCE
+            .iter()
+            .find(|catalog| spec.catalogs.contains(catalog));
+        let opts = match catalog {
+            Some(OptionCatalog::Table) => &mut options.table_opts,
+            Some(OptionCatalog::UserMapping) => &mut options.user_opts,
+            Some(OptionCatalog::Server) => &mut options.server_opts,
+            Some(OptionCatalog::Wrapper) => &mut options.wrapper_opts,
+            _ => continue,
+        };
+
+        opts.insert(spec.name.clone(), default.clone());
+    }
+}
+
+/// Parses a boolean the way Postgres does for `boolean` input
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "t" | "yes" | "y" | "on" | "1" => Some(true),
+        "false" | "f" | "no" | "n" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn unknown_option(name: &str, valid: &[&OptionSpec]) -> FdwError {
+    let error = FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_INVALID_OPTION_NAME,
+        format!("invalid option \"{}\"", name),
+    );
+
+    let closest = valid
+        .iter()
+        .map(|spec| (levenshtein(name, &spec.name), &spec.name))
+        .filter(|(distance, candidate)| *distance <= candidate.len() / 2)
+        .min_by_key(|(distance, _)| *distance);
+
+    if let Some((_, candidate)) = closest {
+        return error.with_hint(format!("Perhaps you meant the option \"{}\".", candidate));
+    }
+
+    if valid.is_empty() {
+        return error.with_hint("There are no valid options in this context.");
+    }
+
+    let names: Vec<&str> = valid.iter().map(|spec| spec.name.as_str()).collect();
+
+    error.with_hint(format!(
+        "Valid options in this context are: {}",
+        names.join(", ")
+    ))
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_bool_options() {
+        assert_eq!(bool::parse_option("on"), Ok(true));
+        assert_eq!(bool::parse_option(" F "), Ok(false));
+        assert!(bool::parse_option("maybe").is_err());
+    }
+
+    #[test]
+    fn parse_duration_options() {
+        assert_eq!(Duration::parse_option("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(
+            Duration::parse_option("1.5min"),
+            Ok(Duration::from_secs(90))
+        );
+        // Milliseconds without a unit
+        assert_eq!(
+            Duration::parse_option("250"),
+            Ok(Duration::from_millis(250))
+        );
+        assert!(Duration::parse_option("2 weeks").is_err());
+        assert!(Duration::parse_option("100000000000000000000000d").is_err());
+    }
+
+    #[test]
+    fn parse_list_options() {
+        assert_eq!(
+            Vec::<String>::parse_option(" a, b,,c "),
+            Ok(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+        );
+    }
+
+    #[test]
+    fn levenshtein_distance() {
+        assert_eq!(levenshtein("host", "host"), 0);
+        assert_eq!(levenshtein("", "host"), 4);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("hots", "host"), 2);
+    }
+
+    fn schema() -> Vec<OptionSpec> {
+        vec![
+            OptionSpec::new("host", OptionType::Text, &[OptionCatalog::Server]).required(),
+            OptionSpec::new("port", OptionType::Integer, &[OptionCatalog::Server]),
+            OptionSpec::new(
+                "timeout",
+                OptionType::Text,
+                &[OptionCatalog::Server, OptionCatalog::Table],
+            )
+            .required(),
+        ]
+    }
+
+    fn options(options: &[&str]) -> Vec<String> {
+        options.iter().map(|option| option.to_string()).collect()
+    }
+
+    #[test]
+    fn validate_accepts_declared_options() {
+        let given = options(&["host=db", "port=5432"]);
+
+        assert!(validate(&schema(), &given, OptionCatalog::Server).is_ok());
+        // Required options valid on several objects may be given on either
+        assert!(validate(&schema(), &[], OptionCatalog::Table).is_ok());
+    }
+
+    #[test]
+    fn validate_suggests_closest_option() {
+        let given = options(&["hots=db"]);
+        let err = validate(&schema(), &given, OptionCatalog::Server).unwrap_err();
+
+        assert_eq!(err.message, "invalid option \"hots\"");
+        assert_eq!(
+            err.hint.as_deref(),
+            Some("Perhaps you meant the option \"host\".")
+        );
+
+        let given = options(&["database=db"]);
+        let err = validate(&schema(), &given, OptionCatalog::Server).unwrap_err();
+
+        assert_eq!(
+            err.hint.as_deref(),
+            Some("Valid options in this context are: host, port, timeout")
+        );
+    }
+
+    #[test]
+    fn validate_required_options() {
+        let given = options(&["port=5432"]);
+        let err = validate(&schema(), &given, OptionCatalog::Server).unwrap_err();
+
+        assert_eq!(err.message, "option \"host\" is required");
+    }
+
+    #[test]
+    fn validate_option_types() {
+        let given = options(&["host=db", "port=many"]);
+        let err = validate(&schema(), &given, OptionCatalog::Server).unwrap_err();
+
+        assert_eq!(err.message, "option \"port\" requires an integer value");
+        assert_eq!(err.detail.as_deref(), Some("Invalid value: \"many\"."));
+
+        let given = options(&["batch_size=0"]);
+
+        assert!(validate(&builtin_schema(), &given, OptionCatalog::Table).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use pgx::*;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// State of a parallel scan shared by the leader and the workers in dynamic shared memory
+#[repr(C)]
+pub(crate) struct ParallelScan {
+    pub partitions: u32,
+    next_partition: AtomicU32,
+}
+
+impl ParallelScan {
+    pub(crate) unsafe fn init(coordinate: *mut Self, partitions: usize) {
+        std::ptr::write(
+            coordinate,
+            ParallelScan {
+                partitions: partitions as u32,
+                next_partition: AtomicU32::new(0),
+            },
+        );
+    }
+
+    pub(crate) fn reset(&self) {
+        self.next_partition.store(0, Ordering::SeqCst);
+    }
+
+    /// Claims the next partition no other process has scanned yet
+    pub(crate) fn claim(&self) -> Option<usize> {
+        let partition = self.next_partition.fetch_add(1, Ordering::SeqCst);
+
+        if partition < self.partitions {
+            Some(partition as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// Share of the rows of a parallel scan each process gets, like `get_parallel_divisor` in
+/// `costsize.c`. The leader participates less the more workers there are.
+pub(crate) fn parallel_divisor(workers: usize) -> f64 {
+    let mut divisor = workers as f64;
+
+    if unsafe { pg_sys::parallel_leader_participation } {
+        let leader_contribution = 1.0 - (0.3 * workers as f64);
+
+        if leader_contribution > 0.0 {
+            divisor += leader_contribution;
+        }
+    }
+
+    divisor
+}
@@ -0,0 +1,414 @@
+use pg_sys::*;
+use pgx::*;
+use std::ffi::CStr;
+
+/// A scan restriction translated from the query's `WHERE` clause.
+///
+/// The quals handed to `ForeignData::execute` are implicitly AND:ed together.
+#[derive(Debug, Clone)]
+pub enum Qual {
+    /// `column <operator> value`, e.g. `id = '1'` or `created < now()`
+    Op {
+        column: String,
+        operator: String,
+        value: QualValue,
+        /// Collation the operator compares with, `None` for types that aren't collatable
+        collation: Option<Collation>,
+    },
+    /// `column <operator> ANY(values)` (`use_or`) or `column <operator> ALL(values)`,
+    /// e.g. `id IN ('1', '2')`
+    ArrayOp {
+        column: String,
+        operator: String,
+        use_or: bool,
+        values: Vec<QualValue>,
+        collation: Option<Collation>,
+    },
+    /// `column IS NULL`, or `column IS NOT NULL` when `negated`
+    IsNull {
+        column: String,
+        negated: bool,
+    },
+    And(Vec<Qual>),
+    Or(Vec<Qual>),
+    Not(Box<Qual>),
+}
+
+/// Collation of a comparison or sort key. Sources that order strings differently from the
+/// collation, e.g. byte by byte, can't be trusted with `<`, `>` or `ORDER BY`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collation {
+    /// Name in `pg_collation`, `default` for the collation of the database
+    pub name: String,
+    /// Whether strings are ordered byte by byte, as with the `C` and `POSIX` collations
+    pub bytewise: bool,
+    pub(crate) oid: Oid,
+}
+
+// Not exported by pgx
+const DEFAULT_COLLATION_OID: Oid = 100;
+const C_COLLATION_OID: Oid = 950;
+const POSIX_COLLATION_OID: Oid = 951;
+
+impl Collation {
+    /// The collation `oid`, `None` when comparing values of types that aren't collatable
+    pub(crate) unsafe fn from_oid(oid: Oid) -> Option<Self> {
+        if oid == InvalidOid {
+            return None;
+        }
+
+        let name = get_collation_name(oid);
+
+        if name.is_null() {
+            return None;
+        }
+
+        let bytewise = match oid {
+            C_COLLATION_OID | POSIX_COLLATION_OID => true,
+            DEFAULT_COLLATION_OID => {
+                let option = crate::cstring("lc_collate");
+                let locale = GetConfigOption(option.as_ptr(), true, false);
+
+                !locale.is_null() && matches!(CStr::from_ptr(locale).to_str(), Ok("C" | "POSIX"))
+            }
+            _ => false,
+        };
+
+        Some(Collation {
+            name: CStr::from_ptr(name).to_string_lossy().into_owned(),
+            bytewise,
+            oid,
+        })
+    }
+}
+
+/// The right hand side of a `Qual`
+#[derive(Debug, Clone, Copy)]
+pub enum QualValue {
+    /// A constant from the query text
+    Const(Option<pg_sys::Datum>, PgOid),
+    /// A value only known at execution time (query parameters, stable function calls, columns
+    /// of the outer side of a nested loop). Always `None` when inspected by
+    /// `ForeignData::supports_qual` during planning.
+    Param(Option<pg_sys::Datum>, PgOid),
+}
+
+impl QualValue {
+    pub fn datum(&self) -> Option<pg_sys::Datum> {
+        match self {
+            QualValue::Const(datum, _) | QualValue::Param(datum, _) => *datum,
+        }
+    }
+
+    pub fn type_oid(&self) -> PgOid {
+        match self {
+            QualValue::Const(_, oid) | QualValue::Param(_, oid) => *oid,
+        }
+    }
+
+    pub fn value<T: FromDatum>(&self) -> Option<T> {
+        self.datum()
+            .and_then(|datum| unsafe { T::from_datum(datum, false, self.type_oid().value()) })
+    }
+}
+
+/// Translates restriction clauses of a single base relation into `Qual`s.
+///
+/// Sub-expressions that aren't `Const`s but can be computed without looking at the scanned
+/// relation, including columns of other relations in parameterized scans, are handed to
+/// `resolve`, which is called in a deterministic order so that the
+/// planner and executor agree on which value belongs to which `QualValue::Param`.
+pub(crate) struct Deparser<F> {
+    relid: Index,
+    columns: Vec<String>,
+    resolve: F,
+}
+
+impl<F: FnMut(*mut Expr) -> QualValue> Deparser<F> {
+    pub(crate) fn new(relid: Index, desc: &PgTupleDesc, resolve: F) -> Self {
+        Self {
+            relid,
+            columns: desc.iter().map(|attr| attr.name().into()).collect(),
+            resolve,
+        }
+    }
+
+    pub(crate) unsafe fn deparse(&mut self, expr: *mut Expr) -> Option<Qual> {
+        let node = expr as *mut Node;
+
+        if is_a(node, NodeTag_T_OpExpr) {
+            self.deparse_op(expr as *mut OpExpr)
+        } else if is_a(node, NodeTag_T_ScalarArrayOpExpr) {
+            self.deparse_array_op(expr as *mut ScalarArrayOpExpr)
+        } else if is_a(node, NodeTag_T_NullTest) {
+            let test = expr as *mut NullTest;
+
+            if (*test).argisrow {
+                return None;
+            }
+
+            self.column(strip_relabel((*test).arg))
+                .map(|column| Qual::IsNull {
+                    column,
+                    negated: (*test).nulltesttype != NullTestType_IS_NULL,
+                })
+        } else if is_a(node, NodeTag_T_BoolExpr) {
+            let bool_expr = expr as *mut BoolExpr;
+            let args = PgList::<Expr>::from_pg((*bool_expr).args)
+                .iter_ptr()
+                .map(|arg| self.deparse(arg))
+                .collect::<Option<Vec<Qual>>>()?;
+
+            match (*bool_expr).boolop {
+                boolop if boolop == BoolExprType_AND_EXPR => Some(Qual::And(args)),
+                boolop if boolop == BoolExprType_OR_EXPR => Some(Qual::Or(args)),
+                _ => args
+                    .into_iter()
+                    .next()
+                    .map(|qual| Qual::Not(Box::new(qual))),
+            }
+        } else if is_a(node, NodeTag_T_Var) && (*(expr as *mut Var)).vartype == BOOLOID {
+            // `WHERE flag` is planned as a bare boolean column
+            self.column(expr).map(|column| Qual::Op {
+                column,
+                operator: "=".into(),
+                value: QualValue::Const(true.into_datum(), PgOid::from(BOOLOID)),
+                collation: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    unsafe fn deparse_op(&mut self, op: *mut OpExpr) -> Option<Qual> {
+        let args = PgList::<Expr>::from_pg((*op).args);
+
+        if args.len() != 2 {
+            return None;
+        }
+
+        let (left, right) = (
+            strip_relabel(args.get_ptr(0)?),
+            strip_relabel(args.get_ptr(1)?),
+        );
+
+        let (column, opno, value) = match (self.column(left), self.column(right)) {
+            (Some(column), None) => (column, (*op).opno, right),
+            (None, Some(column)) => (column, get_commutator((*op).opno), left),
+            _ => return None,
+        };
+
+        let operator = operator_name(opno)?;
+        let value = self.value(value)?;
+
+        Some(Qual::Op {
+            column,
+            operator,
+            value,
+            collation: Collation::from_oid((*op).inputcollid),
+        })
+    }
+
+    unsafe fn deparse_array_op(&mut self, op: *mut ScalarArrayOpExpr) -> Option<Qual> {
+        let args = PgList::<Expr>::from_pg((*op).args);
+        let column = self.column(strip_relabel(args.get_ptr(0)?))?;
+        let array = args.get_ptr(1)?;
+
+        if !is_a(array as *mut Node, NodeTag_T_Const) {
+            return None;
+        }
+
+        let operator = operator_name((*op).opno)?;
+        let values = Self::array_values(array as *mut Const)?;
+
+        Some(Qual::ArrayOp {
+            column,
+            operator,
+            use_or: (*op).useOr,
+            values,
+            collation: Collation::from_oid((*op).inputcollid),
+        })
+    }
+
+    unsafe fn array_values(array: *mut Const) -> Option<Vec<QualValue>> {
+        if (*array).constisnull {
+            return None;
+        }
+
+        let elem_type = get_element_type((*array).consttype);
+        let mut typlen = 0;
+        let mut typbyval = false;
+        let mut typalign = 0;
+        let mut elems = std::ptr::null_mut();
+        let mut nulls = std::ptr::null_mut();
+        let mut nelems = 0;
+
+        get_typlenbyvalalign(elem_type, &mut typlen, &mut typbyval, &mut typalign);
+        deconstruct_array(
+            pg_detoast_datum((*array).constvalue as *mut pg_sys::varlena) as *mut ArrayType,
+            elem_type,
+            typlen as i32,
+            typbyval,
+            typalign,
+            &mut elems,
+            &mut nulls,
+            &mut nelems,
+        );
+
+        let (elems, nulls) = (
+            std::slice::from_raw_parts(elems, nelems as usize),
+            std::slice::from_raw_parts(nulls, nelems as usize),
+        );
+
+        Some(
+            elems
+                .iter()
+                .zip(nulls)
+                .map(|(datum, isnull)| {
+                    QualValue::Const(
+                        if *isnull { None } else { Some(*datum) },
+                        PgOid::from(elem_type),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Name of the scanned relation's column referenced by `expr`, if any
+    unsafe fn column(&self, expr: *mut Expr) -> Option<String> {
+        if !is_a(expr as *mut Node, NodeTag_T_Var) {
+            return None;
+        }
+
+        let var = expr as *mut Var;
+
+        if (*var).varno != self.relid || (*var).varlevelsup != 0 || (*var).varattno <= 0 {
+            return None;
+        }
+
+        self.columns.get((*var).varattno as usize - 1).cloned()
+    }
+
+    pub(crate) unsafe fn value(&mut self, expr: *mut Expr) -> Option<QualValue> {
+        let node = expr as *mut Node;
+
+        if is_a(node, NodeTag_T_Const) {
+            let constant = expr as *mut Const;
+            let datum = if (*constant).constisnull {
+                None
+            } else {
+                Some((*constant).constvalue)
+            };
+
+            Some(QualValue::Const(datum, PgOid::from((*constant).consttype)))
+        } else if bms_is_member(self.relid as i32, pull_varnos(node))
+            || contain_volatile_functions(node)
+            || contain_subplans(node)
+        {
+            None
+        } else {
+            Some((self.resolve)(expr))
+        }
+    }
+}
+
+pub(crate) unsafe fn operator_name(opno: Oid) -> Option<String> {
+    if opno == InvalidOid {
+        return None;
+    }
+
+    let name = get_opname(opno);
+
+    if name.is_null() {
+        return None;
+    }
+
+    CStr::from_ptr(name).to_str().ok().map(String::from)
+}
+
+/// `expr` without any binary compatible casts, e.g. `varchar` to `text`
+pub(crate) unsafe fn strip_relabel(mut expr: *mut Expr) -> *mut Expr {
+    while is_a(expr as *mut Node, NodeTag_T_RelabelType) {
+        expr = (*(expr as *mut RelabelType)).arg;
+    }
+
+    expr
+}
+
+/// Restriction clauses of a scan split into the ones `ForeignData` takes responsibility for
+/// and the ones Postgres has to check locally.
+pub(crate) struct ScanClauses {
+    /// Bare `Expr`s handled by the implementor
+    pub remote_conds: *mut List,
+    /// `RestrictInfo`s checked by Postgres
+    pub local_conds: *mut List,
+    /// Runtime values referenced by `remote_conds`, in the order the executor side
+    /// `Deparser` will ask for them
+    pub params: *mut List,
+    /// `remote_conds` as seen during planning
+    pub quals: Vec<Qual>,
+}
+
+/// Splits `scan_clauses` (a list of `RestrictInfo`) by whether `accept` takes
+/// responsibility for them.
+pub(crate) unsafe fn classify_clauses(
+    relid: Index,
+    desc: &PgTupleDesc,
+    scan_clauses: *mut List,
+    accept: impl Fn(&Qual) -> bool,
+) -> ScanClauses {
+    let mut remote_conds = PgList::<Expr>::new();
+    let mut local_conds = PgList::<RestrictInfo>::new();
+    let mut params = PgList::<Expr>::new();
+    let mut quals = vec![];
+
+    for rinfo in PgList::<RestrictInfo>::from_pg(scan_clauses).iter_ptr() {
+        if (*rinfo).pseudoconstant {
+            continue;
+        }
+
+        let mut clause_params = vec![];
+        let qual = Deparser::new(relid, desc, |expr| {
+            clause_params.push(expr);
+            QualValue::Param(None, PgOid::from(exprType(expr as *mut Node)))
+        })
+        .deparse((*rinfo).clause);
+
+        match qual {
+            Some(qual) if accept(&qual) => {
+                remote_conds.push((*rinfo).clause);
+                clause_params.into_iter().for_each(|expr| params.push(expr));
+                quals.push(qual);
+            }
+            _ => local_conds.push(rinfo),
+        }
+    }
+
+    ScanClauses {
+        remote_conds: remote_conds.into_pg(),
+        local_conds: local_conds.into_pg(),
+        params: params.into_pg(),
+        quals,
+    }
+}
+
+/// Rebuilds the `Qual`s accepted by `classify_clauses` at execution time, filling in
+/// `QualValue::Param`s from `param_values`.
+pub(crate) unsafe fn from_remote_conds(
+    relid: Index,
+    desc: &PgTupleDesc,
+    remote_conds: *mut List,
+    values: &mut impl Iterator<Item = Option<pg_sys::Datum>>,
+) -> Vec<Qual> {
+    let mut deparser = Deparser::new(relid, desc, |expr| {
+        QualValue::Param(
+            values.next().flatten(),
+            PgOid::from(exprType(expr as *mut Node)),
+        )
+    });
+
+    PgList::<Expr>::from_pg(remote_conds)
+        .iter_ptr()
+        .filter_map(|clause| deparser.deparse(clause))
+        .collect()
+}
@@ -0,0 +1,18 @@
+use crate::{ColumnOptions, Limit, Qual, SortKey};
+use pgx::*;
+
+/// A scan of a foreign table, handed to `ForeignData::execute`. Parts of the query that are
+/// pushed down are added here, implementors only need to look at the ones they support.
+pub struct Scan<'a> {
+    pub desc: &'a PgTupleDesc<'a>,
+    /// Options of the columns of `desc`, e.g. to map them to the names of the source
+    pub column_opts: &'a ColumnOptions,
+    /// Restrictions accepted by `ForeignData::supports_qual`, implicitly AND:ed together
+    pub quals: Vec<Qual>,
+    /// Columns the query references, in table order
+    pub columns: Vec<String>,
+    /// Order the rows must be returned in, empty when any order will do
+    pub sort: Vec<SortKey>,
+    /// When set only the rows it selects must be returned
+    pub limit: Option<Limit>,
+}
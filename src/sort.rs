@@ -0,0 +1,105 @@
+use crate::Collation;
+use pg_sys::*;
+use pgx::*;
+
+/// Ordering of the rows returned by `ForeignData::execute`, one per `ORDER BY` key. Keys
+/// always use the default btree ordering of the column's type.
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub column: String,
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub type_oid: PgOid,
+    /// Collation strings are ordered by, `None` for types that aren't collatable
+    pub collation: Option<Collation>,
+}
+
+// Not exported by pgx
+const BTREE_AM_OID: Oid = 403;
+
+/// Translates `pathkeys` into `(attnum, SortKey)` pairs, or `None` if any of the keys isn't
+/// a plain column of the relation `relid` or doesn't use the default ordering of its type.
+pub(crate) unsafe fn from_pathkeys(
+    pathkeys: *mut List,
+    relid: Index,
+    desc: &PgTupleDesc,
+) -> Option<Vec<(AttrNumber, SortKey)>> {
+    PgList::<PathKey>::from_pg(pathkeys)
+        .iter_ptr()
+        .map(|pathkey| {
+            let eclass = (*pathkey).pk_eclass;
+
+            if (*eclass).ec_has_volatile {
+                return None;
+            }
+
+            let attnum = PgList::<EquivalenceMember>::from_pg((*eclass).ec_members)
+                .iter_ptr()
+                .filter(|member| !(**member).em_is_child)
+                .map(|member| crate::qual::strip_relabel((*member).em_expr))
+                .filter(|expr| is_a(*expr as *mut Node, NodeTag_T_Var))
+                .map(|expr| expr as *mut Var)
+                .find(|var| (**var).varno == relid && (**var).varlevelsup == 0)
+                .map(|var| (*var).varattno)
+                .filter(|attnum| *attnum > 0)?;
+
+            let attr = desc.get(attnum as usize - 1)?;
+            let opclass = GetDefaultOpClass(attr.atttypid, BTREE_AM_OID);
+
+            if opclass == InvalidOid || (*pathkey).pk_opfamily != get_opclass_family(opclass) {
+                return None;
+            }
+
+            Some((
+                attnum,
+                SortKey {
+                    column: attr.name().into(),
+                    descending: (*pathkey).pk_strategy == BTGreaterStrategyNumber as i32,
+                    nulls_first: (*pathkey).pk_nulls_first,
+                    type_oid: PgOid::from(attr.atttypid),
+                    collation: Collation::from_oid((*eclass).ec_collation),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Encodes sort keys as a `List` of `(attnum, descending, nulls_first, collation)` integer
+/// lists, so they can be stored in `fdw_private`.
+pub(crate) unsafe fn to_list(keys: &[(AttrNumber, SortKey)]) -> *mut List {
+    let mut list = PgList::<List>::new();
+
+    for (attnum, key) in keys {
+        let collation = key
+            .collation
+            .as_ref()
+            .map_or(InvalidOid, |collation| collation.oid);
+        let mut item = pg_sys::lappend_int(std::ptr::null_mut(), *attnum as i32);
+        item = pg_sys::lappend_int(item, key.descending as i32);
+        item = pg_sys::lappend_int(item, key.nulls_first as i32);
+        item = pg_sys::lappend_int(item, collation as i32);
+
+        list.push(item);
+    }
+
+    list.into_pg()
+}
+
+/// Decodes a `List` created by `to_list`
+pub(crate) unsafe fn from_list(list: *mut List, desc: &PgTupleDesc) -> Vec<SortKey> {
+    PgList::<List>::from_pg(list)
+        .iter_ptr()
+        .filter_map(|item| {
+            let item = PgList::<List>::from_pg(item);
+            let attr = desc.get(item.get_int(0)? as usize - 1)?;
+
+            Some(SortKey {
+                column: attr.name().into(),
+                descending: item.get_int(1)? != 0,
+                nulls_first: item.get_int(2)? != 0,
+                type_oid: PgOid::from(attr.atttypid),
+                collation: Collation::from_oid(item.get_int(3)? as Oid),
+            })
+        })
+        .collect()
+}